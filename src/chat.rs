@@ -0,0 +1,303 @@
+// Chat log rendering, for turning saved chat into caption/subtitle files
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::config::{ChatExportFormat, ConfigChat, YTTAlignment, YTTAnchor};
+use crate::error::Error;
+use crate::index::{ArchiveIndex, ContentKind};
+use crate::timestamp;
+use crate::vodbot_api::{ChatLog, ChatMessage, StageData, VideoSlice};
+
+// Overlay positions are given on a 1080p canvas, libass scales it to the video
+const ASS_WIDTH: u32 = 1920;
+const ASS_HEIGHT: u32 = 1080;
+
+// Twitch's default name colors, handed out to chatters who never picked one
+const DEFAULT_COLORS: [&str; 15] = [
+    "#FF0000", "#0000FF", "#008000", "#B22222", "#FF7F50", "#9ACD32", "#FF4500", "#2E8B57",
+    "#DAA520", "#D2691E", "#5F9EA0", "#1E90FF", "#FF69B4", "#8A2BE2", "#00FF7F",
+];
+
+// Gather the chat of every slice in a stage, with offsets moved so they line
+// up with the slices played back to back.
+pub fn stage_chat(index: &ArchiveIndex, stage: &StageData) -> Result<Vec<ChatMessage>, Error> {
+    let mut messages = Vec::new();
+    let mut slice_start = 0;
+
+    for s in &stage.slices {
+        messages.extend(slice_chat(index, s)?.into_iter().map(|mut f| {
+            f.offset += slice_start;
+            f
+        }));
+        slice_start += timestamp::slice_time(&s.to)?.saturating_sub(timestamp::slice_time(&s.ss)?);
+    }
+
+    Ok(messages)
+}
+
+// The chat of one slice, with offsets from the start of the slice
+pub fn slice_chat(index: &ArchiveIndex, slice: &VideoSlice) -> Result<Vec<ChatMessage>, Error> {
+    let ss = timestamp::slice_time(&slice.ss)?;
+    let to = timestamp::slice_time(&slice.to)?;
+
+    match index.get(ContentKind::Chat, &slice.video_id) {
+        Some(e) => Ok(ChatLog::load(&e.path)?
+            .messages
+            .into_iter()
+            .filter(|f| f.offset >= ss && f.offset < to)
+            .map(|mut f| {
+                f.offset -= ss;
+                f
+            })
+            .collect()),
+        None => {
+            log::info!("no chat archived for {}, skipping", slice.video_id);
+            Ok(Vec::new())
+        }
+    }
+}
+
+pub fn extension(format: &ChatExportFormat) -> &'static str {
+    match format {
+        ChatExportFormat::Raw => "json",
+        ChatExportFormat::Ytt => "ytt",
+        ChatExportFormat::Rt => "rt",
+        ChatExportFormat::Sami => "smi",
+    }
+}
+
+pub fn render(conf: &ConfigChat, messages: &[ChatMessage]) -> String {
+    match conf.export_format {
+        ChatExportFormat::Raw => serde_json::to_string(messages).unwrap(),
+        ChatExportFormat::Ytt => render_ytt(conf, messages),
+        ChatExportFormat::Rt => render_rt(conf, messages),
+        ChatExportFormat::Sami => render_sami(conf, messages),
+    }
+}
+
+pub fn name_color(conf: &ConfigChat, m: &ChatMessage) -> String {
+    if !m.color.is_empty() {
+        return m.color.to_uppercase();
+    }
+    if !conf.randomize_uncolored_names {
+        return "#FFFFFF".to_owned();
+    }
+
+    // the same chatter should get the same color in every export
+    let h = m
+        .user_name
+        .bytes()
+        .fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize));
+    DEFAULT_COLORS[h % DEFAULT_COLORS.len()].to_owned()
+}
+
+fn render_ytt(conf: &ConfigChat, messages: &[ChatMessage]) -> String {
+    let anchor = match conf.ytt_anchor {
+        YTTAnchor::TopLeft => 0,
+        YTTAnchor::TopCenter => 1,
+        YTTAnchor::TopRight => 2,
+        YTTAnchor::CenterLeft => 3,
+        YTTAnchor::CenterCenter => 4,
+        YTTAnchor::CenterRight => 5,
+        YTTAnchor::BottomLeft => 6,
+        YTTAnchor::BottomCenter => 7,
+        YTTAnchor::BottomRight => 8,
+    };
+    let align = match conf.ytt_align {
+        YTTAlignment::Left => 0,
+        YTTAlignment::Right => 1,
+        YTTAlignment::Center => 2,
+    };
+
+    // one pen per name color, pen 0 is plain white for the messages themselves
+    let mut pens: HashMap<String, usize> = HashMap::new();
+    let mut pen_defs = String::from("<pen id=\"0\" fc=\"#FFFFFF\"/>");
+    let mut body = String::new();
+    let duration = conf.message_display_time * 1000;
+
+    for m in messages {
+        let color = name_color(conf, m);
+        let next = pens.len() + 1;
+        let pen = *pens.entry(color.clone()).or_insert_with(|| {
+            pen_defs += &format!("<pen id=\"{}\" fc=\"{}\"/>", next, color);
+            next
+        });
+
+        body += &format!(
+            "<p t=\"{}\" d=\"{}\" wp=\"0\" ws=\"0\"><s p=\"{}\">{}</s><s p=\"0\">: {}</s></p>\n",
+            m.offset * 1000,
+            duration,
+            pen,
+            escape(&m.user_name),
+            escape(&m.msg)
+        );
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<timedtext format=\"3\">\n<head>{}<wp id=\"0\" ap=\"{}\" ah=\"{}\" av=\"{}\"/><ws id=\"0\" ju=\"{}\"/></head>\n<body>\n{}</body>\n</timedtext>\n",
+        pen_defs, anchor, conf.ytt_position_x, conf.ytt_position_y, align, body
+    )
+}
+
+fn render_rt(conf: &ConfigChat, messages: &[ChatMessage]) -> String {
+    let mut body = String::new();
+    for m in messages {
+        body += &format!(
+            "<time begin=\"{}\" end=\"{}\"/><clear/><font color=\"{}\">{}</font>: {}\n",
+            rt_time(m.offset),
+            rt_time(m.offset + conf.message_display_time),
+            name_color(conf, m),
+            escape(&m.user_name),
+            escape(&m.msg)
+        );
+    }
+
+    format!(
+        "<window type=\"generic\" wordwrap=\"true\" bgcolor=\"black\">\n{}</window>\n",
+        body
+    )
+}
+
+fn render_sami(conf: &ConfigChat, messages: &[ChatMessage]) -> String {
+    let mut body = String::new();
+    for m in messages {
+        body += &format!(
+            "<SYNC Start={}><P Class=ENCC><font color=\"{}\">{}</font>: {}</P></SYNC>\n",
+            m.offset * 1000,
+            name_color(conf, m),
+            escape(&m.user_name),
+            escape(&m.msg)
+        );
+        body += &format!(
+            "<SYNC Start={}><P Class=ENCC>&nbsp;</P></SYNC>\n",
+            (m.offset + conf.message_display_time) * 1000
+        );
+    }
+
+    format!(
+        "<SAMI>\n<HEAD>\n<STYLE TYPE=\"text/css\"><!--\nP {{ font-family: sans-serif; color: white; }}\n.ENCC {{ Name: English; lang: en-US; }}\n--></STYLE>\n</HEAD>\n<BODY>\n{}</BODY>\n</SAMI>\n",
+        body
+    )
+}
+
+// Render chat as an ASS script for burning in, drawn like a chat replay panel.
+// A new event starts whenever a message comes in or times out, showing the
+// newest messages still on screen, so older ones scroll up and off the top.
+pub fn render_ass(conf: &ConfigChat, messages: &[ChatMessage]) -> String {
+    let margin_r = ASS_WIDTH.saturating_sub(conf.overlay_x + conf.overlay_width);
+    let margin_v = ASS_HEIGHT.saturating_sub(conf.overlay_y + conf.overlay_height);
+    let back = format!("&H{:02X}000000", 255 - conf.overlay_background_opacity);
+
+    let mut out = format!(
+        "[Script Info]\nScriptType: v4.00+\nPlayResX: {}\nPlayResY: {}\nWrapStyle: 0\nScaledBorderAndShadow: yes\n\n\
+        [V4+ Styles]\nFormat: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+        Style: Chat,{},{},&H00FFFFFF,&H00FFFFFF,{b},{b},0,0,0,0,100,100,0,0,3,6,0,1,{},{},{},1\n\n\
+        [Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        ASS_WIDTH,
+        ASS_HEIGHT,
+        conf.overlay_font,
+        conf.overlay_font_size,
+        conf.overlay_x,
+        margin_r,
+        margin_v,
+        b = back
+    );
+
+    let lines: Vec<String> = messages
+        .iter()
+        .map(|m| {
+            format!(
+                "{{\\b1\\c{}}}{}{{\\b0\\c&HFFFFFF&}}: {}",
+                ass_color(&name_color(conf, m)),
+                ass_escape(&m.user_name),
+                ass_escape(&m.msg)
+            )
+        })
+        .collect();
+
+    let mut times: Vec<usize> = messages
+        .iter()
+        .flat_map(|m| [m.offset, m.offset + conf.message_display_time])
+        .collect();
+    times.sort_unstable();
+    times.dedup();
+
+    // messages come sorted by offset and all stay up for the same time, so
+    // they leave in the order they came in and the visible ones are a window
+    // sliding along the list
+    let mut visible: VecDeque<usize> = VecDeque::new();
+    let mut next = 0;
+    for w in times.windows(2) {
+        let (start, end) = (w[0], w[1]);
+        while next < messages.len() && messages[next].offset <= start {
+            visible.push_back(next);
+            next += 1;
+        }
+        while visible
+            .front()
+            .is_some_and(|&i| messages[i].offset + conf.message_display_time <= start)
+        {
+            visible.pop_front();
+        }
+        if visible.is_empty() {
+            continue;
+        }
+
+        let shown: Vec<&str> = visible
+            .iter()
+            .skip(visible.len().saturating_sub(conf.overlay_max_lines))
+            .map(|&i| lines[i].as_str())
+            .collect();
+        out += &format!(
+            "Dialogue: 0,{},{},Chat,,0,0,0,,{}\n",
+            ass_time(start),
+            ass_time(end),
+            shown.join("\\N")
+        );
+    }
+
+    out
+}
+
+fn rt_time(seconds: usize) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.00",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+fn ass_time(seconds: usize) -> String {
+    format!(
+        "{}:{:02}:{:02}.00",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+// ASS colors are blue, green, red, the other way around from HTML
+fn ass_color(html: &str) -> String {
+    let hex = html.trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return "&HFFFFFF&".to_owned();
+    }
+    format!("&H{}{}{}&", &hex[4..6], &hex[2..4], &hex[0..2])
+}
+
+// Braces start override tags and backslashes start escapes, neither of which
+// chat should be able to do
+fn ass_escape(s: &str) -> String {
+    s.replace('\\', "\\\u{200B}")
+        .replace('{', "(")
+        .replace('}', ")")
+        .replace(['\n', '\r'], " ")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -4,6 +4,8 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand, ValueEnum};
 
+use crate::index::ContentKind;
+
 #[derive(Debug, Parser)]
 #[command(name = "VodBot", author, version)]
 #[command(about, long_about = None)]
@@ -48,10 +50,95 @@ pub enum Commands {
         #[command(subcommand)]
         command: StageMode,
     },
+    #[command(about = "Search archived videos, clips and chat logs")]
+    Search {
+        #[arg(
+            long,
+            value_enum,
+            help = "Kinds of videos to include, use --chat to search chat logs"
+        )]
+        kind: Vec<ContentKind>,
+        #[arg(long, help = "Logins of the channels to include")]
+        streamer: Vec<String>,
+        #[arg(long, help = "Text to match in the game name")]
+        game: Option<String>,
+        #[arg(long, help = "Text to match in the title")]
+        title: Option<String>,
+        #[arg(
+            long,
+            help = "Only include content created on or after this date (YYYY-MM-DD)"
+        )]
+        after: Option<String>,
+        #[arg(
+            long,
+            help = "Only include content created before this date (YYYY-MM-DD)"
+        )]
+        before: Option<String>,
+        #[arg(long, help = "Minimum length in seconds")]
+        min_duration: Option<usize>,
+        #[arg(long, help = "Maximum length in seconds")]
+        max_duration: Option<usize>,
+        #[arg(long, help = "Text to match in saved chat messages")]
+        chat: Option<String>,
+
+        #[arg(short, long, help = "Output results as JSON")]
+        json: bool,
+        #[arg(long, help = "Output only IDs, for use with `stage new`")]
+        ids_only: bool,
+    },
+    #[command(about = "Delete archived content according to retention rules")]
+    Prune {
+        #[arg(help = "Channels to prune, defaults to all in the config")]
+        channels: Vec<String>,
+
+        #[arg(long, help = "Only show what would be deleted")]
+        dry_run: bool,
+        #[arg(short = 'y', help = "Confirm deletion")]
+        confirm: bool,
+        #[arg(long, help = "Keep chat logs, even for pruned videos")]
+        keep_chat: bool,
+        #[arg(long, help = "Keep meta files, only deleting the media")]
+        keep_meta: bool,
+    },
+    #[command(about = "Summarize archive contents and storage use")]
+    Stats {
+        #[arg(short, long, help = "Output stats as JSON")]
+        json: bool,
+        #[arg(long, default_value_t = 10, help = "Number of largest items to list")]
+        top: usize,
+    },
+    #[command(about = "Rebuild the archive index from meta files on disk")]
+    Reindex,
     #[command(about = "Export staged data to local storage")]
-    Export { stage_id: String, path: PathBuf },
+    Export {
+        stage_id: String,
+        path: PathBuf,
+
+        #[arg(long, help = "Export preset to use, overriding the stage's")]
+        preset: Option<String>,
+    },
     #[command(about = "Upload staged data to YouTube")]
-    Upload { stage_id: String },
+    Upload {
+        stage_id: String,
+
+        #[arg(long, help = "Export preset to use, overriding the stage's")]
+        preset: Option<String>,
+    },
+    #[command(about = "Log in to a service VodBot uploads to")]
+    Login {
+        #[arg(value_enum)]
+        service: LoginService,
+    },
+    #[command(about = "Log out of a service, revoking VodBot's access")]
+    Logout {
+        #[arg(value_enum)]
+        service: LoginService,
+    },
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoginService {
+    Youtube,
 }
 
 #[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
@@ -68,26 +155,59 @@ pub enum StageMode {
         #[arg(help = "ID(s) of videos (VODs, Clips, etc)")]
         ids: Vec<String>,
 
+        #[arg(
+            long,
+            conflicts_with_all = ["ids", "from_clip", "ss", "to"],
+            help = "Make one slice per chapter of this VOD"
+        )]
+        from_chapters: Option<String>,
+        #[arg(
+            long,
+            requires = "from_chapters",
+            help = "Only use chapters of this game"
+        )]
+        game: Option<String>,
+        #[arg(
+            long,
+            conflicts_with_all = ["ids", "ss", "to"],
+            help = "Slice the archived source VOD around these clip(s)"
+        )]
+        from_clip: Option<Vec<String>>,
+        #[arg(
+            long,
+            requires = "from_clip",
+            help = "Time to add on both ends of a clip's slice"
+        )]
+        pad: Option<String>,
+
         #[arg(long, help = "Names of the channels involved in the video")]
         streamers: Option<Vec<String>>,
         #[arg(long, help = "Title of the final video")]
         title: Option<String>,
         #[arg(long, help = "Description of the final video")]
         description: Option<String>,
-        #[arg(long, help = "Starting time of video slice")]
+        #[arg(long, help = "Export preset to use for this stage")]
+        preset: Option<String>,
+        #[arg(
+            long,
+            help = "Starting time of video slice, as H:MM:SS, 1h2m3s or chapter:N"
+        )]
         ss: Option<Vec<String>>,
-        #[arg(long, help = "Ending time of video slice")]
+        #[arg(
+            long,
+            help = "Ending time of video slice, as H:MM:SS, 1h2m3s, chapter:N or +length"
+        )]
         to: Option<Vec<String>>,
-        // #[arg(long)]
-        // tn_heads: Option<Vec<String>>,
-        // #[arg(long)]
-        // tn_game: Option<String>,
-        // #[arg(long)]
-        // tn_text: Option<String>,
-        // #[arg(long)]
-        // tn_video_idx: Option<usize>,
-        // #[arg(long)]
-        // tn_timestamp: Option<String>,
+        #[arg(long, help = "Thumbnail head image names, in position order")]
+        tn_heads: Option<Vec<String>>,
+        #[arg(long, help = "Thumbnail game icon name")]
+        tn_game: Option<String>,
+        #[arg(long, help = "Thumbnail overlay text")]
+        tn_text: Option<String>,
+        #[arg(long, help = "Index of the slice to grab the thumbnail frame from")]
+        tn_video_idx: Option<usize>,
+        #[arg(long, help = "Time in the slice to grab the thumbnail frame from")]
+        tn_timestamp: Option<String>,
     },
     #[command(about = "Remove staged data")]
     Remove {
@@ -97,9 +217,52 @@ pub enum StageMode {
         #[arg(short = 'y', help = "Confirm removal")]
         confirm: bool,
     },
+    #[command(about = "Change staged data in place")]
+    Edit {
+        #[arg(help = "ID of staged data")]
+        id: String,
+
+        #[arg(long, help = "New title of the final video")]
+        title: Option<String>,
+        #[arg(long, help = "New description of the final video")]
+        description: Option<String>,
+        #[arg(long, help = "New names of the channels involved in the video")]
+        streamers: Option<Vec<String>>,
+        #[arg(long, help = "New export preset to use for this stage")]
+        preset: Option<String>,
+        #[arg(long, help = "Index(es) of slices to remove, counting from 0")]
+        remove: Option<Vec<usize>>,
+        #[arg(long, help = "ID(s) of videos to add as slices at the end")]
+        add: Option<Vec<String>>,
+        #[arg(long, requires = "add", help = "Starting time of added slice")]
+        ss: Option<Vec<String>>,
+        #[arg(long, requires = "add", help = "Ending time of added slice")]
+        to: Option<Vec<String>>,
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "New order of slices by index, after removing and adding, like 2,0,1"
+        )]
+        order: Option<Vec<usize>>,
+
+        #[arg(
+            short,
+            long,
+            help = "Open the stage in $EDITOR, after any other changes"
+        )]
+        editor: bool,
+        #[arg(long, value_enum, default_value_t = EditFormat::Yaml, help = "Format to edit the stage in")]
+        format: EditFormat,
+    },
     #[command(about = "List current staged data")]
     List {
         #[arg(help = "ID(s) of staged data")]
         ids: Option<Vec<String>>,
     },
 }
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EditFormat {
+    Yaml,
+    Json,
+}
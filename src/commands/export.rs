@@ -0,0 +1,225 @@
+// Export command, for cutting and joining a stage's slices into one video
+
+use crate::chat;
+use crate::config::{load_config, Config, ConfigExportPreset, ExportMode};
+use crate::error::Error;
+use crate::index::ArchiveIndex;
+use crate::thumbnail;
+use crate::util::{create_dir, ffmpeg_filter_path, run_ffmpeg};
+use crate::vodbot_api::StageData;
+
+use std::path::{Path, PathBuf};
+
+pub fn run(
+    config_path: PathBuf,
+    stage_id: String,
+    path: PathBuf,
+    preset: Option<String>,
+) -> Result<(), Error> {
+    let conf = load_config(&config_path)?;
+    let stage = StageData::load(&conf.directories, &stage_id)?;
+    let preset = select_preset(&conf, &stage, preset.as_deref())?;
+
+    // a directory gets a file named after the stage, anything else is the file itself
+    let output = if path.is_dir() {
+        path.join(format!("{}.mkv", stage.id))
+    } else {
+        path
+    };
+
+    println!(
+        "Exporting stage `{}` to `{}` ...",
+        stage.id,
+        output.display()
+    );
+    export_stage(&conf, &stage, preset, &output)?;
+
+    if conf.export.thumbnail_enable && stage.thumbnail.is_some() {
+        let thumbnail_path = output.with_extension("png");
+        println!("Rendering thumbnail to `{}` ...", thumbnail_path.display());
+        thumbnail::render(&conf, &stage, &thumbnail_path)?;
+    }
+
+    if conf.stage.delete_on_export {
+        stage.remove(&conf.directories)?;
+    }
+
+    println!("Done!");
+
+    Ok(())
+}
+
+// The command line wins over the stage, which wins over the config
+pub fn select_preset<'a>(
+    conf: &'a Config,
+    stage: &StageData,
+    name: Option<&str>,
+) -> Result<&'a ConfigExportPreset, Error> {
+    let name = name
+        .or(stage.preset.as_deref())
+        .unwrap_or(&conf.export.preset);
+    conf.export.preset(name)
+}
+
+pub fn export_stage(
+    conf: &Config,
+    stage: &StageData,
+    preset: &ConfigExportPreset,
+    output: &Path,
+) -> Result<(), Error> {
+    if stage.slices.is_empty() {
+        return Err(Error::input(format!(
+            "Stage `{}` has no slices to export.",
+            stage.id
+        )));
+    }
+    if let Some(p) = output.parent() {
+        create_dir(p)?;
+    }
+
+    if preset.chat_overlay && preset.mode != ExportMode::H264 {
+        return Err(Error::input(
+            "Chat can only be burned in with the `h264` export mode.",
+        ));
+    }
+    let index = match preset.chat_overlay {
+        true => Some(ArchiveIndex::load(conf)?),
+        false => None,
+    };
+
+    let temp_dir = conf.directories.temp.join(format!("stage_{}", stage.id));
+    create_dir(&temp_dir)?;
+
+    // cut each slice out on its own, then join them all up with the concat
+    // demuxer. every part comes out of the same encoder settings, so joining
+    // them never needs another encode
+    let mut list = String::new();
+    for (i, s) in stage.slices.iter().enumerate() {
+        let part = temp_dir.join(format!("{}.mkv", i));
+
+        let mut overlay = None;
+        if let Some(index) = &index {
+            let path = temp_dir.join(format!("{}.ass", i));
+            let ass = chat::render_ass(&conf.chat, &chat::slice_chat(index, s)?);
+            std::fs::write(&path, ass).map_err(|why| {
+                Error::io(format!("Failed to write chat overlay, reason \"{}\".", why))
+                    .with_source(why)
+            })?;
+            overlay = Some(path);
+        }
+
+        let mut args = vec![
+            "-ss",
+            &s.ss,
+            "-to",
+            &s.to,
+            "-i",
+            s.filepath.to_str().unwrap(),
+        ];
+        let codec = slice_args(preset, overlay.as_deref());
+        args.extend(codec.iter().map(|f| f.as_str()));
+        args.push(part.to_str().unwrap());
+        run_ffmpeg(conf, &args)?;
+
+        list += &format!("file '{}'\n", part.to_str().unwrap().replace('\'', "'\\''"));
+    }
+
+    let list_path = temp_dir.join("concat.txt");
+    std::fs::write(&list_path, list).map_err(|why| {
+        Error::io(format!("Failed to write concat list, reason \"{}\".", why)).with_source(why)
+    })?;
+
+    let mut args = vec![
+        "-f",
+        "concat",
+        "-safe",
+        "0",
+        "-i",
+        list_path.to_str().unwrap(),
+    ];
+    let codec = join_args(preset);
+    args.extend(codec.iter().map(|f| f.as_str()));
+    args.push(output.to_str().unwrap());
+    run_ffmpeg(conf, &args)?;
+
+    std::fs::remove_dir_all(&temp_dir).map_err(|why| {
+        Error::io(format!(
+            "Failed to clean up after export, reason \"{}\".",
+            why
+        ))
+        .with_source(why)
+    })
+}
+
+// Stream copies can only cut on keyframes. Anything re-encoded gets decoded
+// from the keyframe before `-ss` and trimmed, so its cuts are frame accurate.
+fn slice_args(preset: &ConfigExportPreset, overlay: Option<&Path>) -> Vec<String> {
+    let mut args: Vec<String> = match preset.mode {
+        ExportMode::Copy => vec!["-c", "copy", "-avoid_negative_ts", "make_zero"],
+        ExportMode::H264 => vec!["-c:v", "libx264", "-pix_fmt", "yuv420p"],
+        ExportMode::AudioOnly => vec!["-vn"],
+    }
+    .into_iter()
+    .map(String::from)
+    .collect();
+
+    if preset.mode == ExportMode::H264 {
+        args.extend([
+            "-preset".to_owned(),
+            preset.speed.clone(),
+            "-crf".to_owned(),
+            preset.crf.to_string(),
+        ]);
+        // scale first, so the chat is drawn at the final size
+        let mut filters = Vec::new();
+        if let Some(h) = preset.scale_height {
+            filters.push(format!("scale=-2:{}", h));
+        }
+        if let Some(p) = overlay {
+            filters.push(format!("ass={}", ffmpeg_filter_path(p)));
+        }
+        if !filters.is_empty() {
+            args.extend(["-vf".to_owned(), filters.join(",")]);
+        }
+    }
+    if preset.mode != ExportMode::Copy {
+        args.extend(audio_args(preset));
+    }
+
+    args
+}
+
+// Loudness is measured over the whole video, so it's done once all the slices
+// are joined, rather than slice by slice.
+fn join_args(preset: &ConfigExportPreset) -> Vec<String> {
+    if !preset.loudnorm {
+        return vec!["-c".to_owned(), "copy".to_owned()];
+    }
+
+    let mut args = match preset.mode {
+        ExportMode::AudioOnly => vec!["-vn".to_owned()],
+        _ => vec!["-c:v".to_owned(), "copy".to_owned()],
+    };
+    args.extend([
+        "-af".to_owned(),
+        format!(
+            "loudnorm=I={}:TP={}:LRA={}",
+            preset.loudnorm_target, preset.loudnorm_true_peak, preset.loudnorm_range
+        ),
+        // loudnorm resamples to 192kHz to find true peaks, bring it back down
+        "-ar".to_owned(),
+        "48000".to_owned(),
+    ]);
+    args.extend(audio_args(preset));
+
+    args
+}
+
+fn audio_args(preset: &ConfigExportPreset) -> [String; 4] {
+    [
+        "-c:a".to_owned(),
+        "aac".to_owned(),
+        "-b:a".to_owned(),
+        preset.audio_bitrate.clone(),
+    ]
+}
@@ -1,9 +1,11 @@
 // Info command, for getting basic data on various things
 
 use crate::config::load_config;
+use crate::error::Error;
 use crate::gql::GQLClient;
+use crate::index::{ArchiveIndex, ContentKind, IndexEntry};
 use crate::twitch;
-use crate::util::ExitMsg;
+use crate::util::format_size;
 
 use regex::Regex;
 use std::path::PathBuf;
@@ -16,21 +18,21 @@ enum ContentType {
 }
 
 #[rustfmt::skip]
-fn test(client: &GQLClient) -> Result<(), ExitMsg> {
+fn test(client: &GQLClient) -> Result<(), Error> {
     // println!(" ~! VIDEOS !~ \n\n");
     // println!("channel_videos:    \n{:?}\n", twitch::get_channel_videos(&client, "vodbot_fti".to_owned())?);
     // println!("channels_videos:   \n{:?}\n", twitch::get_channels_videos(&client, vec!["vodbot_fti".to_owned()])?);
     println!("video_chapters:    \n{:?}\n", twitch::get_video_chapters(&client, "1818343419".to_owned())?);
-    println!("videos_chapters:   \n{:?}\n", twitch::get_videos_chapters(&client, &vec!["1818343419".to_owned()])?);
+    println!("videos_chapters:   \n{:?}\n", twitch::get_videos_chapters(&client, &["1818343419".to_owned()])?);
     println!("video_comments:    \n{:?}\n", twitch::get_video_comments(&client, "1818343419".to_owned())?);
-    println!("videos_comments:   \n{:?}\n", twitch::get_videos_comments(&client, &vec!["1818343419".to_owned()])?);
+    println!("videos_comments:   \n{:?}\n", twitch::get_videos_comments(&client, &["1818343419".to_owned()])?);
     println!("video_pba_token:   \n{:?}\n", twitch::get_video_playback_access_token(&client, "1818343419".to_owned())?);
-    println!("videos_pba_tokens: \n{:?}\n", twitch::get_videos_playback_access_tokens(&client, &vec!["1811624369".to_owned()])?);
+    println!("videos_pba_tokens: \n{:?}\n", twitch::get_videos_playback_access_tokens(&client, &["1811624369".to_owned()])?);
     // println!(" ~! CLIPS !~ \n\n");
     println!("channel_clips:     \n{:?}\n", twitch::get_channel_clips(&client, "vodbot_fti".to_owned())?);
-    println!("channels_clips:    \n{:?}\n", twitch::get_channels_clips(&client, &vec!["vodbot_fti".to_owned()])?);
+    println!("channels_clips:    \n{:?}\n", twitch::get_channels_clips(&client, &["vodbot_fti".to_owned()])?);
     println!("clip_pba_token:    \n{:?}\n", twitch::get_clip_playback_access_token(&client, "SourHardLEDDBstyle-NMdErh41r1IN9cjm".to_owned())?);
-    println!("clip_pba_tokens:   \n{:?}\n", twitch::get_clips_playback_access_tokens(&client, &vec!["SourHardLEDDBstyle-NMdErh41r1IN9cjm".to_owned()])?);
+    println!("clip_pba_tokens:   \n{:?}\n", twitch::get_clips_playback_access_tokens(&client, &["SourHardLEDDBstyle-NMdErh41r1IN9cjm".to_owned()])?);
     // println!("                   \n\n");
     // println!(" ~! GENERIC !~ \n\n");
     println!("get_channel:       \n{:?}\n", twitch::get_channel(&client, "vodbot_fti".to_owned())?);
@@ -40,7 +42,7 @@ fn test(client: &GQLClient) -> Result<(), ExitMsg> {
     Ok(())
 }
 
-pub fn run(config_path: PathBuf, _json: bool, ids: Vec<String>) -> Result<(), ExitMsg> {
+pub fn run(config_path: PathBuf, _json: bool, ids: Vec<String>) -> Result<(), Error> {
     let set = [
         (ContentType::Video, Regex::new(r"^(?P<id>\d+)?$").unwrap()),
         (ContentType::Video, Regex::new(r"^(https?://)?(www\.)?twitch.tv/videos/(?P<id>\d+)(\?.*)?$").unwrap()),
@@ -63,7 +65,8 @@ pub fn run(config_path: PathBuf, _json: bool, ids: Vec<String>) -> Result<(), Ex
         })
     });
 
-    let client = GQLClient::new(conf.pull.gql_client_id);
+    let index = ArchiveIndex::load(&conf)?;
+    let client = GQLClient::new(&conf)?;
     test(&client)?;
 
     for i in s {
@@ -75,6 +78,9 @@ pub fn run(config_path: PathBuf, _json: bool, ids: Vec<String>) -> Result<(), Ex
                 println!("get_channel: \n{:?}\n", r);
             }
             ContentType::Video => {
+                if let Some(e) = index.find_video(&j) {
+                    print_archived(e);
+                }
                 let r = twitch::get_video(&client, j.clone())?;
                 if let None = r {
                     let r = twitch::get_channel(&client, j)?;
@@ -84,6 +90,9 @@ pub fn run(config_path: PathBuf, _json: bool, ids: Vec<String>) -> Result<(), Ex
                 }
             }
             ContentType::Clip => {
+                if let Some(e) = index.get(ContentKind::Clip, &j) {
+                    print_archived(e);
+                }
                 let r = twitch::get_clip(&client, j)?;
                 println!("get_clip: \n{:?}\n", r);
             }
@@ -92,3 +101,13 @@ pub fn run(config_path: PathBuf, _json: bool, ids: Vec<String>) -> Result<(), Ex
 
     Ok(())
 }
+
+fn print_archived(e: &IndexEntry) {
+    println!(
+        "archived {} `{}` at `{}` ({})",
+        e.kind.noun(),
+        e.id,
+        e.path.display(),
+        format_size(e.size, 1, true)
+    );
+}
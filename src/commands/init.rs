@@ -1,12 +1,13 @@
 // Init command, initializes all the default folders and files for use
 
 use crate::config;
+use crate::error::Error;
 use crate::util;
 
 use std::fs::File;
 use std::io::prelude::*;
 
-pub fn run(overwrite_confirm: bool) -> Result<(), util::ExitMsg> {
+pub fn run(overwrite_confirm: bool) -> Result<(), Error> {
     let conf = config::Config {
         ..Default::default()
     };
@@ -37,34 +38,27 @@ pub fn run(overwrite_confirm: bool) -> Result<(), util::ExitMsg> {
     util::create_dir(&conf.directories.thumbnail)?;
 
     let mut config_file = File::create(&config_path).map_err(|why| {
-        util::ExitMsg::new(
-            util::ExitCode::InitCannotOpenConfig,
-            format!(
-                "Failed to open file to write config to `{}`, reason: \"{}\".",
-                &config_path.display(),
-                why
-            ),
-        )
+        Error::io(format!(
+            "Failed to open file to write config to `{}`, reason: \"{}\".",
+            &config_path.display(),
+            why
+        ))
+        .with_source(why)
     })?;
 
     let json_to_write = serde_json::to_string_pretty(&conf).map_err(|why| {
-        util::ExitMsg::new(
-            util::ExitCode::InitCannotSerializeConfig,
-            format!("Failed to serialize config, reason: \"{}\".", why),
-        )
+        Error::config(format!("Failed to serialize config, reason: \"{}\".", why)).with_source(why)
     })?;
 
     config_file
         .write_all(json_to_write.as_bytes())
         .map_err(|why| {
-            util::ExitMsg::new(
-                util::ExitCode::InitCannotWriteConfig,
-                format!(
-                    "Failed to write config to `{}`, reason: \"{}\".",
-                    &config_path.display(),
-                    why
-                ),
-            )
+            Error::io(format!(
+                "Failed to write config to `{}`, reason: \"{}\".",
+                &config_path.display(),
+                why
+            ))
+            .with_source(why)
         })?;
 
     // println!("Done!");
@@ -0,0 +1,24 @@
+// Login command, for authorizing VodBot to upload on a user's behalf
+
+use crate::cli::LoginService;
+use crate::config::load_config;
+use crate::error::Error;
+use crate::oauth;
+
+use std::path::PathBuf;
+
+pub fn run(config_path: PathBuf, service: LoginService) -> Result<(), Error> {
+    let conf = load_config(&config_path)?;
+
+    match service {
+        LoginService::Youtube => {
+            oauth::login(&conf)?;
+            println!(
+                "Logged in to YouTube, session saved to `{}`.",
+                conf.upload.session_path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+// Logout command, for revoking and forgetting a saved session
+
+use crate::cli::LoginService;
+use crate::config::load_config;
+use crate::error::Error;
+use crate::oauth;
+
+use std::path::PathBuf;
+
+pub fn run(config_path: PathBuf, service: LoginService) -> Result<(), Error> {
+    let conf = load_config(&config_path)?;
+
+    match service {
+        LoginService::Youtube => {
+            oauth::logout(&conf)?;
+            println!("Logged out of YouTube.");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+// Prune command, for deleting archived content according to retention rules
+
+use crate::config::{load_config, Config, ConfigRetentionRule};
+use crate::error::Error;
+use crate::index::{ArchiveIndex, ContentKind, IndexEntry};
+use crate::util::{format_size, print_table};
+
+use chrono::{DateTime, Duration, Utc};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct PruneAction {
+    pub kind: ContentKind,
+    pub id: String,
+    pub streamer: String,
+    pub created_at: String,
+    pub size: usize,
+    pub reason: String,
+}
+
+pub fn run(
+    config_path: PathBuf,
+    channels: Vec<String>,
+    dry_run: bool,
+    confirm: bool,
+    keep_chat: bool,
+    keep_meta: bool,
+) -> Result<(), Error> {
+    let conf = load_config(&config_path)?;
+    let mut index = ArchiveIndex::load(&conf)?;
+
+    let mut actions = plan(&conf, &index, &channels);
+    if keep_chat {
+        actions.retain(|f| f.kind != ContentKind::Chat);
+    }
+
+    if actions.is_empty() {
+        println!("Nothing to prune.");
+        return Ok(());
+    }
+
+    let rows: Vec<_> = actions
+        .iter()
+        .map(|f| {
+            vec![
+                f.kind.noun().to_owned(),
+                f.id.clone(),
+                f.streamer.clone(),
+                f.created_at.clone(),
+                format_size(f.size, 1, true),
+                f.reason.clone(),
+            ]
+        })
+        .collect();
+    print_table(&["KIND", "ID", "STREAMER", "DATE", "SIZE", "REASON"], &rows);
+
+    let total: usize = actions.iter().map(|f| f.size).sum();
+    println!(
+        "\n{} items to prune, freeing {}.",
+        actions.len(),
+        format_size(total, 1, true)
+    );
+
+    if dry_run {
+        println!("Dry run, nothing was deleted.");
+        return Ok(());
+    }
+
+    if !confirm && !casual::confirm("Are you sure you want to delete these?") {
+        println!("Exiting...");
+        return Ok(());
+    }
+
+    for a in &actions {
+        // chat may have already gone along with its video
+        let e = match index.get(a.kind, &a.id).cloned() {
+            Some(e) => e,
+            None => continue,
+        };
+        prune_entry(&mut index, e, keep_meta)?;
+
+        // chat goes along with its video, unless asked to stay
+        if !keep_chat && a.kind.is_video() {
+            if let Some(c) = index.get(ContentKind::Chat, &a.id).cloned() {
+                prune_entry(&mut index, c, keep_meta)?;
+            }
+        }
+    }
+
+    println!("Pruned {} items.", actions.len());
+
+    Ok(())
+}
+
+// Work out what would be deleted, without touching anything. Channels that
+// aren't in the config are never pruned.
+pub fn plan(conf: &Config, index: &ArchiveIndex, only: &[String]) -> Vec<PruneAction> {
+    let now = Utc::now();
+    let mut actions = Vec::new();
+
+    for c in &conf.channels {
+        if !only.is_empty() && !only.iter().any(|f| f.eq_ignore_ascii_case(&c.username)) {
+            continue;
+        }
+
+        for kind in ContentKind::ALL {
+            let mut entries: Vec<_> = index
+                .entries_of(kind)
+                .filter(|f| f.streamer.eq_ignore_ascii_case(&c.username))
+                .collect();
+            // newest first, so "keep the last N" and size caps drop the oldest
+            entries.sort_by_key(|e| std::cmp::Reverse(e.meta_str("createdAt")));
+
+            apply_rule(c.retention.rule(kind), &entries, now, &mut actions);
+        }
+    }
+
+    actions
+}
+
+fn apply_rule(
+    rule: &ConfigRetentionRule,
+    entries: &[&IndexEntry],
+    now: DateTime<Utc>,
+    actions: &mut Vec<PruneAction>,
+) {
+    let mut kept_size = 0;
+
+    for (i, e) in entries.iter().enumerate() {
+        let age = DateTime::parse_from_rfc3339(&e.meta_str("createdAt"))
+            .map(|f| now.signed_duration_since(f))
+            .ok();
+
+        let reason = if rule.keep_last.is_some_and(|n| i >= n) {
+            Some(format!("not in the last {}", rule.keep_last.unwrap()))
+        } else if rule
+            .keep_days
+            .is_some_and(|d| age.is_some_and(|a| a > Duration::days(d as i64)))
+        {
+            Some(format!("older than {} days", rule.keep_days.unwrap()))
+        } else if e.kind == ContentKind::Clip
+            && rule
+                .min_views
+                .is_some_and(|v| e.meta_usize("viewCount") < v)
+        {
+            Some(format!("fewer than {} views", rule.min_views.unwrap()))
+        } else if rule.max_size.is_some_and(|s| kept_size + e.size > s) {
+            Some(format!(
+                "over size cap of {}",
+                format_size(rule.max_size.unwrap(), 1, true)
+            ))
+        } else {
+            None
+        };
+
+        match reason {
+            Some(r) => actions.push(PruneAction {
+                kind: e.kind,
+                id: e.id.clone(),
+                streamer: e.streamer.clone(),
+                created_at: e.meta_str("createdAt"),
+                size: e.size,
+                reason: r,
+            }),
+            None => kept_size += e.size,
+        }
+    }
+}
+
+fn prune_entry(index: &mut ArchiveIndex, e: IndexEntry, keep_meta: bool) -> Result<(), Error> {
+    log::debug!("pruning {:?} {}", e.kind, e.id);
+
+    remove_file(&e.path)?;
+    if !keep_meta {
+        remove_file(&e.meta_path())?;
+    }
+
+    // keep a tombstone, so the next pull doesn't bring it right back
+    index.insert(IndexEntry::new_pruned(e.kind, e.path, e.meta)?)
+}
+
+fn remove_file(path: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(path) {
+        Err(why) if why.kind() != std::io::ErrorKind::NotFound => Err(Error::io(format!(
+            "Failed to remove `{}`, reason: \"{}\".",
+            path.display(),
+            why
+        ))
+        .with_source(why)),
+        _ => Ok(()),
+    }
+}
@@ -2,17 +2,22 @@
 
 use crate::cli::PullMode;
 use crate::config::{load_config, Config, ConfigChannel};
+use crate::error::Error;
 use crate::gql::GQLClient;
+use crate::http::HttpTransport;
+use crate::index::{meta_path_for, ArchiveIndex, ContentKind, IndexEntry};
 use crate::itd;
-use crate::twitch;
-use crate::util::{create_dir, get_meta_ids, ExitCode, ExitMsg};
+use crate::progress::{DownloadProgress, ProgressObserver};
+use crate::twitch::{self, BatchResult};
+use crate::util::{create_dir, format_size};
 use crate::vodbot_api::{ChatLog, Clip, PlaybackAccessToken, Vod, VodBotData};
 
-use reqwest::blocking::Client;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
-pub fn run(config_path: PathBuf, _mode: PullMode) -> Result<(), ExitMsg> {
+pub fn run(config_path: PathBuf, _mode: PullMode) -> Result<(), Error> {
     let conf = load_config(&config_path)?;
     let c = &conf.channels;
 
@@ -26,39 +31,60 @@ pub fn run(config_path: PathBuf, _mode: PullMode) -> Result<(), ExitMsg> {
     let users_want_chat: Vec<_> = c.iter().filter(|f| f.save_chat).map(f).collect();
     println!("Checking users: {} ...", users.join(", "));
 
-    let client = GQLClient::new(conf.pull.gql_client_id.clone());
+    let client = GQLClient::new(&conf)?;
 
-    let mut vods = twitch::get_channels_videos_archive(&client, &users_want_vods)?;
-    let mut highlights = twitch::get_channels_videos_highlight(&client, &users_want_highlights)?;
-    let mut premieres = twitch::get_channels_videos_premiere(&client, &users_want_premieres)?;
-    let mut uploads = twitch::get_channels_videos_upload(&client, &users_want_uploads)?;
-    let mut clips = twitch::get_channels_clips(&client, &users_want_clips)?;
+    // channels twitch can't find are skipped, instead of failing everyone else
+    let mut missing = Vec::new();
+    let mut vods = found(
+        twitch::get_channels_videos_archive(&client, &users_want_vods)?,
+        &mut missing,
+    );
+    let mut highlights = found(
+        twitch::get_channels_videos_highlight(&client, &users_want_highlights)?,
+        &mut missing,
+    );
+    let mut premieres = found(
+        twitch::get_channels_videos_premiere(&client, &users_want_premieres)?,
+        &mut missing,
+    );
+    let mut uploads = found(
+        twitch::get_channels_videos_upload(&client, &users_want_uploads)?,
+        &mut missing,
+    );
+    let mut clips = found(
+        twitch::get_channels_clips(&client, &users_want_clips)?,
+        &mut missing,
+    );
+    if !missing.is_empty() {
+        println!(
+            "Could not find these channels on Twitch, were they renamed or banned? {}",
+            missing.join(", ")
+        );
+    }
 
+    // chat logs take their metadata from the vod they belong to
+    let chat_vods: HashMap<_, _> = vods
+        .values()
+        .flatten()
+        .map(|f| (f.id.clone(), f.clone()))
+        .collect();
     let mut chat = HashMap::<String, Vec<String>>::new();
     for k in &users_want_chat {
-        let vods = vods.get(k).unwrap();
-        let vod_ids: Vec<_> = vods.iter().map(|f| f.id.clone()).collect();
-        chat.insert(k.clone(), vod_ids);
+        if let Some(vods) = vods.get(k) {
+            let vod_ids: Vec<_> = vods.iter().map(|f| f.id.clone()).collect();
+            chat.insert(k.clone(), vod_ids);
+        }
     }
 
-    // filter out a bunch of already-downloaded 
-    for k in &users {
-        let dir = &conf.directories;
-        let d = (
-            get_meta_ids(dir.vods.clone().join(&k))?,
-            get_meta_ids(dir.highlights.clone().join(&k))?,
-            get_meta_ids(dir.premieres.clone().join(&k))?,
-            get_meta_ids(dir.uploads.clone().join(&k))?,
-            get_meta_ids(dir.clips.clone().join(&k))?,
-            get_meta_ids(dir.chat.clone().join(&k))?,
-        );
-        d.0.into_iter().for_each(|v| vods.get_mut(k).unwrap().retain(|f| f.id != v));
-        d.1.into_iter().for_each(|v| highlights.get_mut(k).unwrap().retain(|f| f.id != v));
-        d.2.into_iter().for_each(|v| premieres.get_mut(k).unwrap().retain(|f| f.id != v));
-        d.3.into_iter().for_each(|v| uploads.get_mut(k).unwrap().retain(|f| f.id != v));
-        d.4.into_iter().for_each(|v| clips.get_mut(k).unwrap().retain(|f| f.slug != v));
-        d.5.into_iter().for_each(|v| chat.get_mut(k).unwrap().retain(|f| f.to_owned() != v));
-    }
+    // filter out a bunch of already-downloaded
+    let mut index = ArchiveIndex::load(&conf)?;
+    let i = &index;
+    vods.values_mut().for_each(|v| v.retain(|f| !i.contains(ContentKind::Vod, &f.id)));
+    highlights.values_mut().for_each(|v| v.retain(|f| !i.contains(ContentKind::Highlight, &f.id)));
+    premieres.values_mut().for_each(|v| v.retain(|f| !i.contains(ContentKind::Premiere, &f.id)));
+    uploads.values_mut().for_each(|v| v.retain(|f| !i.contains(ContentKind::Upload, &f.id)));
+    clips.values_mut().for_each(|v| v.retain(|f| !i.contains(ContentKind::Clip, &f.slug)));
+    chat.values_mut().for_each(|v| v.retain(|f| !i.contains(ContentKind::Chat, f)));
 
     let vods_count: HashMap<_, _> = vods.iter().map(|(k, v)| (k, v.len())).collect();
     let vods_total: usize = vods_count.values().into_iter().sum();
@@ -109,11 +135,10 @@ pub fn run(config_path: PathBuf, _mode: PullMode) -> Result<(), ExitMsg> {
     println!("Total: {}", total_total);
     println!("");
 
-    // create a new client (for making generic http requests)
-    let genclient = reqwest::blocking::Client::new();
+    // downloads share the gql transport, so they go through the same proxy and pool
+    let transport = client.transport();
 
     // now to download each set of videos per user
-    let dir = &conf.directories;
     for k in &users {
         let count = user_counts.get(k).unwrap_or(&(0, 0, 0, 0, 0, 0));
         let user_total = count.0 + count.1 + count.2 + count.3 + count.4 + count.5;
@@ -122,83 +147,78 @@ pub fn run(config_path: PathBuf, _mode: PullMode) -> Result<(), ExitMsg> {
         }
 
         println!("Pulling {} videos for `{}` ...", user_total, k);
+        let progress = PullProgress::new(k, user_total - count.5);
+        let ctx = PullContext {
+            conf: &conf,
+            client: &client,
+            transport: &transport,
+            progress: &progress,
+        };
 
         // Vods
         download_stuff::<Vod>(
-            dir.vods.clone(),
+            ContentKind::Vod,
             k,
             &mut vods,
             twitch::get_videos_playback_access_tokens,
             itd::download_vod,
-            &conf,
-            &client,
-            &genclient,
-            "Vod".to_owned(),
+            &ctx,
+            &mut index,
         )?;
         // Chatlogs
-        // TODO: take `chat` variable, grab logs downloaded for `k`, and save it to disk.
         let chat_ids = chat.remove(k);
-        if let Some(chat_ids) = chat_ids {
+        if let Some(chat_ids) = chat_ids.filter(|f| !f.is_empty()) {
             let v = twitch::get_videos_comments(&client, &chat_ids)?;
-            // TODO: move this bit into twitch.rs?
-            let v: Vec<_> = v
-                .into_iter()
-                .map(|(u, v)| ChatLog {
-                    video_id: u,
-                    messages: v,
-                })
-                .collect();
-            // TODO: save each in v as chat log file
+            for id in &v.missing {
+                println!("Could not find the chat of video `{}` on Twitch.", id);
+            }
+            for (u, m) in v.found {
+                let vod = chat_vods.get(&u).unwrap();
+                save_chat(&conf, ChatLog::from_data(vod, m), &mut index)?;
+            }
         }
 
         // Highlights
         download_stuff::<Vod>(
-            dir.highlights.clone(),
+            ContentKind::Highlight,
             k,
             &mut highlights,
             twitch::get_videos_playback_access_tokens,
             itd::download_vod,
-            &conf,
-            &client,
-            &genclient,
-            "Highlight".to_owned(),
+            &ctx,
+            &mut index,
         )?;
         // Premiere
         download_stuff::<Vod>(
-            dir.premieres.clone(),
+            ContentKind::Premiere,
             k,
             &mut premieres,
             twitch::get_videos_playback_access_tokens,
             itd::download_vod,
-            &conf,
-            &client,
-            &genclient,
-            "Premiere".to_owned(),
+            &ctx,
+            &mut index,
         )?;
         // Upload
         download_stuff::<Vod>(
-            dir.uploads.clone(),
+            ContentKind::Upload,
             k,
             &mut uploads,
             twitch::get_videos_playback_access_tokens,
             itd::download_vod,
-            &conf,
-            &client,
-            &genclient,
-            "Upload".to_owned(),
+            &ctx,
+            &mut index,
         )?;
         // Clip
         download_stuff::<Clip>(
-            dir.clips.clone(),
+            ContentKind::Clip,
             k,
             &mut clips,
             twitch::get_clips_playback_access_tokens,
             itd::download_clip,
-            &conf,
-            &client,
-            &genclient,
-            "Clip".to_owned(),
+            &ctx,
+            &mut index,
         )?;
+        progress.done();
     }
 
     // println!("Done!");
@@ -206,52 +226,92 @@ pub fn run(config_path: PathBuf, _mode: PullMode) -> Result<(), ExitMsg> {
     Ok(())
 }
 
+fn found<R>(r: BatchResult<R>, missing: &mut Vec<String>) -> HashMap<String, Vec<R>> {
+    for m in r.missing {
+        if !missing.contains(&m) {
+            missing.push(m);
+        }
+    }
+    r.found
+}
+
+// What every download for a user shares
+struct PullContext<'a> {
+    conf: &'a Config,
+    client: &'a GQLClient,
+    transport: &'a Arc<dyn HttpTransport>,
+    progress: &'a PullProgress,
+}
+
 fn download_stuff<T: VodBotData + serde::Serialize>(
-    output_dir: PathBuf,
+    kind: ContentKind,
     user_id: &String,
     content: &mut HashMap<String, Vec<T>>,
     token_method: impl FnOnce(
         &GQLClient,
-        &Vec<String>,
-    ) -> Result<HashMap<String, PlaybackAccessToken>, ExitMsg>,
+        &[String],
+    ) -> Result<HashMap<String, PlaybackAccessToken>, Error>,
     download_method: impl Fn(
         &Config,
         T,
         PlaybackAccessToken,
         PathBuf,
-        &Client,
+        &Arc<dyn HttpTransport>,
         String,
-    ) -> Result<T, ExitMsg>,
-    conf: &Config,
-    gqlclient: &GQLClient,
-    genclient: &Client,
-    noun: String,
-) -> Result<(), ExitMsg> {
+        &dyn ProgressObserver,
+    ) -> Result<T, Error>,
+    ctx: &PullContext,
+    index: &mut ArchiveIndex,
+) -> Result<(), Error> {
+    let conf = ctx.conf;
+    let noun = kind.noun().to_owned();
     let content = content.remove(user_id);
     if content.is_none() {
         log::trace!("not downloading {}'s for {}, as none new were found", noun, user_id);
         return Ok(());
     }
     let content = content.unwrap();
-    let tokens = token_method(gqlclient, &content.iter().map(|f| f.identifier()).collect())?;
+    let tokens = token_method(
+        ctx.client,
+        &content.iter().map(|f| f.identifier()).collect::<Vec<_>>(),
+    )?;
 
+    let output_dir = kind.directory(&conf.directories);
     let mut has_content = false;
     for c in content {
         has_content = true;
-        let output_path = output_dir.clone().join(user_id);
-        create_dir(&output_path)?;
-        let mut output_path = output_path.join(c.filename());
-        let token = tokens.get(&c.identifier()).unwrap().to_owned();
-        let c = download_method(conf, c, token, output_path.clone(), genclient, noun.clone())?;
-
-        output_path.set_extension("meta.json");
-        let file = std::fs::File::create(output_path).map_err(|why| {
-            ExitMsg::new(
-                ExitCode::PullCannotOpenMeta,
-                format!("Failed to open meta to write, reason `{}`.", why),
-            )
+        // Twitch answers null for videos that were deleted or are sub-only
+        let token = match tokens.get(&c.identifier()) {
+            Some(t) => t.to_owned(),
+            None => {
+                println!(
+                    "Could not get access to {} `{}` on Twitch, skipping it.",
+                    noun,
+                    c.identifier()
+                );
+                continue;
+            }
+        };
+        let output_path = output_dir.join(c.output_path(&conf.directories)?);
+        create_dir(output_path.parent().unwrap())?;
+        let c = download_method(
+            conf,
+            c,
+            token,
+            output_path.clone(),
+            ctx.transport,
+            noun.clone(),
+            ctx.progress,
+        )?;
+
+        let meta = serde_json::to_value(&c).unwrap();
+        let meta_path = meta_path_for(&output_path, kind);
+        let file = std::fs::File::create(meta_path).map_err(|why| {
+            Error::io(format!("Failed to open meta to write, reason `{}`.", why)).with_source(why)
         })?;
-        serde_json::to_writer(file, &c).unwrap();
+        serde_json::to_writer(file, &meta).unwrap();
+
+        index.insert(IndexEntry::new(kind, output_path, meta)?)?;
     }
     if has_content {
         println!("");
@@ -259,3 +319,115 @@ fn download_stuff<T: VodBotData + serde::Serialize>(
 
     Ok(())
 }
+
+fn save_chat(conf: &Config, log: ChatLog, index: &mut ArchiveIndex) -> Result<(), Error> {
+    let kind = ContentKind::Chat;
+    let output_path = kind
+        .directory(&conf.directories)
+        .join(log.output_path(&conf.directories)?);
+    create_dir(output_path.parent().unwrap())?;
+
+    println!("Chatlog `{}` -- {} messages", log.video_id, log.message_count);
+
+    let meta = log.meta();
+    let write = |path: &PathBuf, json: String| {
+        std::fs::write(path, json).map_err(|why| {
+            Error::io(format!(
+                "Failed to write chat log to `{}`, reason `{}`.",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })
+    };
+    write(&output_path, serde_json::to_string(&log).unwrap())?;
+    write(
+        &meta_path_for(&output_path, kind),
+        serde_json::to_string(&meta).unwrap(),
+    )?;
+
+    index.insert(IndexEntry::new(kind, output_path, meta)?)
+}
+
+// Progress bars for a user's downloads, one for everything and one for each
+// video as it goes. Bars are hidden when stderr isn't a terminal, so only the
+// finished lines end up in logs.
+struct PullProgress {
+    bars: MultiProgress,
+    total: ProgressBar,
+    items: Mutex<HashMap<String, (String, ProgressBar)>>,
+}
+impl PullProgress {
+    fn new(user: &str, count: usize) -> Self {
+        let bars = MultiProgress::new();
+        let total = bars.add(ProgressBar::new(count as u64));
+        total.set_style(
+            ProgressStyle::with_template("{prefix} [{bar:30}] {pos}/{len} videos")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        total.set_prefix(format!("`{}`", user));
+
+        Self {
+            bars,
+            total,
+            items: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn done(&self) {
+        self.total.finish_and_clear();
+    }
+}
+impl ProgressObserver for PullProgress {
+    fn started(&self, noun: &str, id: &str) {
+        let bar = self.bars.add(ProgressBar::new(1));
+        bar.set_style(
+            ProgressStyle::with_template("{prefix} [{bar:30}] {percent:>3}% -- {msg}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        bar.set_prefix(format!("{} `{}`", noun, id));
+        self.items
+            .lock()
+            .unwrap()
+            .insert(id.to_owned(), (noun.to_owned(), bar));
+    }
+
+    fn progress(&self, p: &DownloadProgress) {
+        if let Some((_, bar)) = self.items.lock().unwrap().get(&p.id) {
+            bar.set_length(p.parts_total as u64);
+            bar.set_position(p.parts_done as u64);
+            bar.set_message(format!(
+                "{} of {} (@ {}/s) -- ({:.0}s left)",
+                format_size(p.bytes, 1, true),
+                format_size(p.estimated_bytes, 1, true),
+                format_size(p.bytes_per_second, 1, true),
+                p.seconds_left
+            ));
+        }
+    }
+
+    fn finished(&self, id: &str, bytes: usize, seconds: f32) {
+        let (noun, bar) = match self.items.lock().unwrap().remove(id) {
+            Some(i) => i,
+            None => return,
+        };
+        bar.finish_and_clear();
+        self.bars.remove(&bar);
+        self.total.inc(1);
+
+        let line = format!(
+            "{} `{}` -- {: >8} in {:.1} seconds",
+            noun,
+            id,
+            format_size(bytes, 1, true),
+            seconds
+        );
+        if self.bars.is_hidden() {
+            println!("{}", line);
+        } else {
+            let _ = self.bars.println(line);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// Reindex command, for rebuilding the archive index from meta files on disk
+
+use crate::config::load_config;
+use crate::error::Error;
+use crate::index::{ArchiveIndex, ContentKind};
+use crate::util::format_size;
+
+use std::path::PathBuf;
+
+pub fn run(config_path: PathBuf) -> Result<(), Error> {
+    let conf = load_config(&config_path)?;
+
+    println!(
+        "Rebuilding index at `{}` ...",
+        conf.directories.index.display()
+    );
+
+    let index = ArchiveIndex::rebuild(&conf)?;
+
+    for kind in ContentKind::ALL {
+        let (count, size) = index
+            .entries_of(kind)
+            .fold((0, 0), |(c, s), e| (c + 1, s + e.size));
+        println!(
+            "{}s: {} ({})",
+            kind.noun(),
+            count,
+            format_size(size, 1, true)
+        );
+    }
+    println!("Total: {}", index.len());
+
+    Ok(())
+}
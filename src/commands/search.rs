@@ -0,0 +1,195 @@
+// Search command, for querying the archive index
+
+use crate::config::load_config;
+use crate::error::Error;
+use crate::index::{ArchiveIndex, ContentKind, IndexEntry};
+use crate::util::{format_duration, print_table, truncate};
+use crate::vodbot_api::ChatLog;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Default)]
+pub struct SearchFilter {
+    pub kinds: Vec<ContentKind>,
+    pub streamers: Vec<String>,
+    pub game: Option<String>,
+    pub title: Option<String>,
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub min_duration: Option<usize>,
+    pub max_duration: Option<usize>,
+    pub chat: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub kind: ContentKind,
+    pub id: String,
+    pub streamer: String,
+    pub title: String,
+    pub game_name: String,
+    pub created_at: String,
+    pub duration: usize,
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub chat_matches: Vec<ChatMatch>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMatch {
+    pub offset: usize,
+    pub user_name: String,
+    pub msg: String,
+}
+
+pub fn run(
+    config_path: PathBuf,
+    filter: SearchFilter,
+    json: bool,
+    ids_only: bool,
+) -> Result<(), Error> {
+    let conf = load_config(&config_path)?;
+    let index = ArchiveIndex::load(&conf)?;
+
+    let results = search(&index, &filter)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results).unwrap());
+    } else if ids_only {
+        // ready to be handed straight to `stage new`
+        let ids: Vec<_> = results.iter().map(|f| f.id.as_str()).collect();
+        println!("{}", ids.join(" "));
+    } else {
+        let rows: Vec<_> = results
+            .iter()
+            .map(|f| {
+                vec![
+                    f.kind.noun().to_owned(),
+                    f.id.clone(),
+                    f.streamer.clone(),
+                    f.created_at.clone(),
+                    format_duration(f.duration),
+                    truncate(&f.game_name, 24),
+                    truncate(&f.title, 48),
+                ]
+            })
+            .collect();
+        print_table(
+            &["KIND", "ID", "STREAMER", "DATE", "LENGTH", "GAME", "TITLE"],
+            &rows,
+        );
+
+        for r in results.iter().filter(|f| !f.chat_matches.is_empty()) {
+            println!("\nChat matches in `{}`:", r.id);
+            for m in &r.chat_matches {
+                println!("  [{}] {}: {}", format_duration(m.offset), m.user_name, m.msg);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn search(
+    index: &ArchiveIndex,
+    filter: &SearchFilter,
+) -> Result<Vec<SearchResult>, Error> {
+    // chat logs aren't results of their own, `--chat` searches inside them
+    if filter.kinds.contains(&ContentKind::Chat) {
+        return Err(Error::input(
+            "Chat logs can't be searched as a kind, use `--chat` to search inside them.",
+        ));
+    }
+
+    let after = filter.after.as_ref().map(|f| parse_date(f)).transpose()?;
+    let before = filter.before.as_ref().map(|f| parse_date(f)).transpose()?;
+    let game = filter.game.as_ref().map(|f| f.to_lowercase());
+    let title = filter.title.as_ref().map(|f| f.to_lowercase());
+    let chat = filter.chat.as_ref().map(|f| f.to_lowercase());
+    let streamers: Vec<_> = filter.streamers.iter().map(|f| f.to_lowercase()).collect();
+
+    let mut results = Vec::new();
+    for e in index.entries().filter(|f| f.kind.is_video()) {
+        if !filter.kinds.is_empty() && !filter.kinds.contains(&e.kind) {
+            continue;
+        }
+
+        let r = to_result(e);
+        // created_at is always RFC 3339 in UTC, so plain string comparison sorts by time
+        let checks = [
+            streamers.is_empty() || streamers.contains(&r.streamer.to_lowercase()),
+            game.as_ref()
+                .is_none_or(|g| r.game_name.to_lowercase().contains(g)),
+            title
+                .as_ref()
+                .is_none_or(|t| r.title.to_lowercase().contains(t)),
+            after.as_ref().is_none_or(|d| &r.created_at >= d),
+            before.as_ref().is_none_or(|d| &r.created_at < d),
+            filter.min_duration.is_none_or(|d| r.duration >= d),
+            filter.max_duration.is_none_or(|d| r.duration <= d),
+        ];
+        if checks.contains(&false) {
+            continue;
+        }
+
+        results.push(r);
+    }
+
+    if let Some(needle) = chat {
+        let mut matched = Vec::new();
+        for mut r in results {
+            let log = match index.get(ContentKind::Chat, &r.id) {
+                Some(e) => ChatLog::load(&e.path)?,
+                None => continue,
+            };
+            r.chat_matches = log
+                .messages
+                .into_iter()
+                .filter(|f| f.msg.to_lowercase().contains(&needle))
+                .map(|f| ChatMatch {
+                    offset: f.offset,
+                    user_name: f.user_name,
+                    msg: f.msg,
+                })
+                .collect();
+            if !r.chat_matches.is_empty() {
+                matched.push(r);
+            }
+        }
+        results = matched;
+    }
+
+    log::debug!("{} search results for {:?}", results.len(), filter);
+    results.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(results)
+}
+
+fn to_result(e: &IndexEntry) -> SearchResult {
+    SearchResult {
+        kind: e.kind,
+        id: e.id.clone(),
+        streamer: e.streamer.clone(),
+        title: e.meta_str("title"),
+        game_name: e.meta_str("gameName"),
+        created_at: e.meta_str("createdAt"),
+        duration: e.meta_usize("duration"),
+        path: e.path.clone(),
+        chat_matches: Vec::new(),
+    }
+}
+
+fn parse_date(s: &str) -> Result<String, Error> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|f| f.format("%Y-%m-%d").to_string())
+        .map_err(|why| {
+            Error::input(format!(
+                "Invalid date `{}`, expected YYYY-MM-DD, reason: \"{}\".",
+                s, why
+            ))
+            .with_source(why)
+        })
+}
@@ -0,0 +1,690 @@
+// Stage command, for preparing archived videos for export or upload
+
+use crate::cli::{EditFormat, StageMode};
+use crate::config::{load_config, Config};
+use crate::description;
+use crate::error::Error;
+use crate::gql::GQLClient;
+use crate::index::{ArchiveIndex, ContentKind, IndexEntry};
+use crate::template;
+use crate::timestamp;
+use crate::twitch;
+use crate::util::{create_dir, format_duration};
+use crate::vodbot_api::{StageData, ThumbnailData, VideoSlice};
+
+use rand::{distributions::Alphanumeric, Rng};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub fn run(config_path: PathBuf, mode: StageMode) -> Result<(), Error> {
+    let conf = load_config(&config_path)?;
+
+    match mode {
+        StageMode::New {
+            ids,
+            from_chapters,
+            game,
+            from_clip,
+            pad,
+            streamers,
+            title,
+            description,
+            preset,
+            ss,
+            to,
+            tn_heads,
+            tn_game,
+            tn_text,
+            tn_video_idx,
+            tn_timestamp,
+        } => {
+            let thumbnail = new_thumbnail(tn_heads, tn_game, tn_text, tn_video_idx, tn_timestamp);
+            let mut index = ArchiveIndex::load(&conf)?;
+            if let Some(clips) = &from_clip {
+                refresh_clip_offsets(&conf, &mut index, clips)?;
+            }
+            let slices = match (from_chapters, from_clip) {
+                (Some(vod), _) => slices_from_chapters(&index, &vod, game)?,
+                (_, Some(clips)) => slices_from_clips(&index, &clips, pad)?,
+                _ => slices_from_ids(&index, &ids, ss, to)?,
+            };
+            new(
+                &conf,
+                slices,
+                streamers,
+                title,
+                description,
+                preset,
+                thumbnail,
+            )
+        }
+        StageMode::Edit {
+            id,
+            title,
+            description,
+            streamers,
+            preset,
+            remove,
+            add,
+            ss,
+            to,
+            order,
+            editor,
+            format,
+        } => {
+            let changes = StageChanges {
+                title,
+                description,
+                streamers,
+                preset,
+                remove: remove.unwrap_or_default(),
+                add: add.unwrap_or_default(),
+                ss,
+                to,
+                order,
+            };
+            edit(&conf, &id, changes, editor, format)
+        }
+        StageMode::Remove { ids, confirm } => remove(&conf, ids, confirm),
+        StageMode::List { ids } => list(&conf, ids),
+    }
+}
+
+// Each slice comes with the archived video it was asked for by, which is where
+// the default title and streamers come from. That's the clip for a slice cut
+// around a clip, even though the slice itself is of the vod.
+fn new(
+    conf: &Config,
+    slices: Vec<(&IndexEntry, VideoSlice)>,
+    streamers: Option<Vec<String>>,
+    title: Option<String>,
+    description: Option<String>,
+    preset: Option<String>,
+    thumbnail: Option<ThumbnailData>,
+) -> Result<(), Error> {
+    if slices.is_empty() {
+        return Err(Error::input("No videos were given to stage."));
+    }
+
+    if let Some(p) = &preset {
+        conf.export.preset(p)?;
+    }
+    if let Some(t) = &thumbnail {
+        if t.video_slice_idx >= slices.len() {
+            return Err(Error::input(format!(
+                "Thumbnail slice index {} is out of range, there are only {} slices.",
+                t.video_slice_idx,
+                slices.len()
+            )));
+        }
+    }
+
+    let streamers = streamers.unwrap_or_else(|| {
+        let mut s: Vec<String> = Vec::new();
+        for (e, _) in &slices {
+            if !s.contains(&e.streamer) {
+                s.push(e.streamer.clone());
+            }
+        }
+        s
+    });
+
+    let stage = StageData {
+        id: new_stage_id(conf),
+        // stage titles and descriptions are templates, twitch titles aren't
+        title: title.unwrap_or(template::escape(&slices[0].0.meta_str("title"))),
+        description: description.unwrap_or_default(),
+        streamers,
+        thumbnail,
+        preset,
+        slices: slices.into_iter().map(|(_, s)| s).collect(),
+    };
+    stage.save(&conf.directories)?;
+
+    println!(
+        "Created stage `{}` with {} slices.",
+        stage.id,
+        stage.slices.len()
+    );
+
+    Ok(())
+}
+
+fn slices_from_ids<'a>(
+    index: &'a ArchiveIndex,
+    ids: &[String],
+    ss: Option<Vec<String>>,
+    to: Option<Vec<String>>,
+) -> Result<Vec<(&'a IndexEntry, VideoSlice)>, Error> {
+    let ss = ss.unwrap_or_default();
+    let to = to.unwrap_or_default();
+
+    ids.iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let e = find_video(index, id)?;
+            let (start, end) = timestamp::resolve_slice(
+                ss.get(i).map(|f| f.as_str()),
+                to.get(i).map(|f| f.as_str()),
+                &e.chapters(),
+                e.meta_usize("duration"),
+            )
+            .map_err(|mut why| {
+                *why.msg_mut() = format!("Slice of `{}`: {}", e.id, why.msg());
+                why
+            })?;
+
+            Ok((e, new_slice(e, start, end)))
+        })
+        .collect()
+}
+
+// One slice per chapter of the vod, optionally only those of one game
+fn slices_from_chapters<'a>(
+    index: &'a ArchiveIndex,
+    vod_id: &str,
+    game: Option<String>,
+) -> Result<Vec<(&'a IndexEntry, VideoSlice)>, Error> {
+    let e = find_video(index, vod_id)?;
+    let duration = e.meta_usize("duration");
+
+    let slices: Vec<_> = e
+        .chapters()
+        .iter()
+        .filter(|c| {
+            game.as_ref()
+                .is_none_or(|g| c.description.eq_ignore_ascii_case(g))
+        })
+        .filter(|c| c.position < duration)
+        .map(|c| {
+            let end = (c.position + c.duration).min(duration);
+            (e, new_slice(e, c.position, end))
+        })
+        .collect();
+
+    if slices.is_empty() {
+        return Err(Error::input(match game {
+            Some(g) => format!("Video `{}` has no chapters of `{}`.", vod_id, g),
+            None => format!("Video `{}` has no chapters.", vod_id),
+        }));
+    }
+
+    Ok(slices)
+}
+
+// A slice of the archived source vod around each clip, widened by `pad` on
+// both ends, so the clip can be recut at full quality
+fn slices_from_clips<'a>(
+    index: &'a ArchiveIndex,
+    slugs: &[String],
+    pad: Option<String>,
+) -> Result<Vec<(&'a IndexEntry, VideoSlice)>, Error> {
+    let pad = match pad {
+        Some(p) => {
+            timestamp::parse(&p).ok_or_else(|| Error::input(format!("Invalid padding `{}`.", p)))?
+        }
+        None => 0,
+    };
+
+    slugs
+        .iter()
+        .map(|slug| {
+            let clip = index.get(ContentKind::Clip, slug).ok_or_else(|| {
+                Error::not_found(format!(
+                    "Cannot find clip `{}` in the archive, has it been pulled?",
+                    slug
+                ))
+            })?;
+
+            let vod_id = clip.meta_str("vodId");
+            if vod_id.is_empty() {
+                return Err(Error::not_found(format!(
+                    "Clip `{}` has no source video on Twitch.",
+                    slug
+                )));
+            }
+            let vod = find_video(index, &vod_id)?;
+
+            // padding can run off either end of the vod, the clip itself can't
+            let duration = vod.meta_usize("duration");
+            let offset = clip.meta_usize("offset");
+            let start = offset.saturating_sub(pad);
+            let end = offset
+                .saturating_add(clip.meta_usize("duration"))
+                .saturating_add(pad)
+                .min(duration);
+            let (start, end) = timestamp::resolve_slice(
+                Some(&start.to_string()),
+                Some(&end.to_string()),
+                &[],
+                duration,
+            )
+            .map_err(|mut why| {
+                *why.msg_mut() = format!("Slice of clip `{}`: {}", clip.id, why.msg());
+                why
+            })?;
+
+            Ok((clip, new_slice(vod, start, end)))
+        })
+        .collect()
+}
+
+// Clips pulled before offsets were read properly have an offset of 0 in their
+// meta, which would cut from the start of the vod. Those are asked for again,
+// and the real offset is saved to the meta and the index.
+fn refresh_clip_offsets(
+    conf: &Config,
+    index: &mut ArchiveIndex,
+    slugs: &[String],
+) -> Result<(), Error> {
+    let stale: Vec<_> = slugs
+        .iter()
+        .filter_map(|s| index.get(ContentKind::Clip, s))
+        .filter(|e| e.meta_usize("offset") == 0)
+        .cloned()
+        .collect();
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    let client = GQLClient::new(conf)?;
+    for mut e in stale {
+        log::debug!("refreshing offset of clip {}", e.id);
+        let offset = twitch::get_clip(&client, e.id.clone())?
+            .and_then(|c| c.video_offset_seconds)
+            .ok_or_else(|| {
+                Error::not_found(format!(
+                    "Cannot find where clip `{}` is in its video on Twitch.",
+                    e.id
+                ))
+            })?;
+        // some clips really are from the very start
+        if offset == 0 {
+            continue;
+        }
+
+        e.meta["offset"] = offset.into();
+        let meta_path = e.meta_path();
+        fs::write(&meta_path, serde_json::to_string(&e.meta).unwrap()).map_err(|why| {
+            Error::io(format!(
+                "Failed to write meta at `{}`, reason: \"{}\".",
+                meta_path.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+        index.insert(e)?;
+    }
+
+    Ok(())
+}
+
+fn find_video<'a>(index: &'a ArchiveIndex, id: &str) -> Result<&'a IndexEntry, Error> {
+    index.find_video(id).ok_or_else(|| {
+        Error::not_found(format!(
+            "Cannot find video `{}` in the archive, has it been pulled?",
+            id
+        ))
+    })
+}
+
+fn new_slice(e: &IndexEntry, start: usize, end: usize) -> VideoSlice {
+    VideoSlice {
+        video_id: e.id.clone(),
+        ss: format_duration(start),
+        to: format_duration(end),
+        filepath: e.path.clone(),
+    }
+}
+
+// Only stages given some thumbnail argument get a thumbnail at all
+fn new_thumbnail(
+    heads: Option<Vec<String>>,
+    game: Option<String>,
+    text: Option<String>,
+    video_idx: Option<usize>,
+    timestamp: Option<String>,
+) -> Option<ThumbnailData> {
+    if heads.is_none()
+        && game.is_none()
+        && text.is_none()
+        && video_idx.is_none()
+        && timestamp.is_none()
+    {
+        return None;
+    }
+
+    Some(ThumbnailData {
+        heads: heads.unwrap_or_default(),
+        game: game.unwrap_or_default(),
+        text: text.unwrap_or_default(),
+        video_slice_idx: video_idx.unwrap_or(0),
+        timestamp: timestamp.unwrap_or("0:00:00".to_owned()),
+    })
+}
+
+struct StageChanges {
+    title: Option<String>,
+    description: Option<String>,
+    streamers: Option<Vec<String>>,
+    preset: Option<String>,
+    remove: Vec<usize>,
+    add: Vec<String>,
+    ss: Option<Vec<String>>,
+    to: Option<Vec<String>>,
+    order: Option<Vec<usize>>,
+}
+
+fn edit(
+    conf: &Config,
+    id: &str,
+    changes: StageChanges,
+    editor: bool,
+    format: EditFormat,
+) -> Result<(), Error> {
+    let mut stage = StageData::load(&conf.directories, id)?;
+    let index = ArchiveIndex::load(conf)?;
+
+    if let Some(t) = changes.title {
+        stage.title = t;
+    }
+    if let Some(d) = changes.description {
+        stage.description = d;
+    }
+    if let Some(s) = changes.streamers {
+        stage.streamers = s;
+    }
+    // an empty name goes back to the config's default
+    if let Some(p) = changes.preset {
+        stage.preset = Some(p).filter(|f| !f.is_empty());
+    }
+
+    // the thumbnail follows its slice around, instead of its old index
+    let mut slices: Vec<_> = stage.slices.drain(..).enumerate().collect();
+
+    let mut remove = changes.remove;
+    remove.sort_unstable();
+    remove.dedup();
+    if let Some(i) = remove.iter().find(|i| **i >= slices.len()) {
+        return Err(Error::input(format!(
+            "Cannot remove slice {}, there are only {} slices.",
+            i,
+            slices.len()
+        )));
+    }
+    for i in remove.iter().rev() {
+        slices.remove(*i);
+    }
+
+    let added = slices_from_ids(&index, &changes.add, changes.ss, changes.to)?;
+    slices.extend(added.into_iter().map(|(_, s)| (usize::MAX, s)));
+
+    if let Some(order) = changes.order {
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        if !sorted.iter().copied().eq(0..slices.len()) {
+            return Err(Error::input(format!(
+                "Slice order must list every index from 0 to {} once.",
+                slices.len().saturating_sub(1)
+            )));
+        }
+        let mut old: Vec<_> = slices.into_iter().map(Some).collect();
+        slices = order.iter().map(|i| old[*i].take().unwrap()).collect();
+    }
+
+    if let Some(t) = &mut stage.thumbnail {
+        match slices.iter().position(|(i, _)| *i == t.video_slice_idx) {
+            Some(i) => t.video_slice_idx = i,
+            None => {
+                println!("The thumbnail's slice was removed, it now uses the first slice.");
+                t.video_slice_idx = 0;
+                t.timestamp = "0:00:00".to_owned();
+            }
+        }
+    }
+    stage.slices = slices.into_iter().map(|(_, s)| s).collect();
+
+    if editor {
+        stage = edit_in_editor(conf, &index, stage, format)?;
+    } else {
+        check_stage(conf, &index, &mut stage)?;
+    }
+    stage.save(&conf.directories)?;
+
+    println!("Saved stage `{}`.", stage.id);
+
+    Ok(())
+}
+
+// Make sure a changed stage still makes sense, and tidy up its slices
+fn check_stage(conf: &Config, index: &ArchiveIndex, stage: &mut StageData) -> Result<(), Error> {
+    if stage.slices.is_empty() {
+        return Err(Error::input(format!(
+            "Stage `{}` has no slices left.",
+            stage.id
+        )));
+    }
+
+    for s in stage.slices.iter_mut() {
+        let e = find_video(index, &s.video_id)?;
+        let (start, end) = timestamp::resolve_slice(
+            Some(&s.ss),
+            Some(&s.to),
+            &e.chapters(),
+            e.meta_usize("duration"),
+        )
+        .map_err(|mut why| {
+            *why.msg_mut() = format!("Slice of `{}`: {}", e.id, why.msg());
+            why
+        })?;
+        *s = new_slice(e, start, end);
+    }
+
+    if let Some(t) = &stage.thumbnail {
+        if t.video_slice_idx >= stage.slices.len() {
+            return Err(Error::input(format!(
+                "Thumbnail slice index {} is out of range, there are only {} slices.",
+                t.video_slice_idx,
+                stage.slices.len()
+            )));
+        }
+        if timestamp::parse(&t.timestamp).is_none() {
+            return Err(Error::input(format!(
+                "Invalid thumbnail timestamp `{}`.",
+                t.timestamp
+            )));
+        }
+    }
+
+    if let Some(p) = &stage.preset {
+        conf.export.preset(p)?;
+    }
+
+    // catches unbalanced braces and unknown placeholders
+    description::render_stage(conf, index, stage)?;
+
+    Ok(())
+}
+
+// Open the stage in the user's editor until it comes back valid, or they give up
+fn edit_in_editor(
+    conf: &Config,
+    index: &ArchiveIndex,
+    stage: StageData,
+    format: EditFormat,
+) -> Result<StageData, Error> {
+    let (ext, text) = match format {
+        EditFormat::Yaml => ("yaml", serde_yaml::to_string(&stage).unwrap()),
+        EditFormat::Json => ("json", serde_json::to_string_pretty(&stage).unwrap()),
+    };
+    create_dir(&conf.directories.temp)?;
+    let path = conf
+        .directories
+        .temp
+        .join(format!("{}.stage.{}", stage.id, ext));
+    fs::write(&path, text).map_err(|why| {
+        Error::io(format!(
+            "Failed to write stage to `{}`, reason: \"{}\".",
+            path.display(),
+            why
+        ))
+        .with_source(why)
+    })?;
+
+    loop {
+        run_editor(&path)?;
+
+        let edited = fs::read_to_string(&path)
+            .map_err(|why| why.to_string())
+            .and_then(|f| match format {
+                EditFormat::Yaml => {
+                    serde_yaml::from_str::<StageData>(&f).map_err(|e| e.to_string())
+                }
+                EditFormat::Json => {
+                    serde_json::from_str::<StageData>(&f).map_err(|e| e.to_string())
+                }
+            })
+            .map_err(|why| {
+                Error::parse(format!(
+                    "Failed to parse edited stage, reason: \"{}\".",
+                    why
+                ))
+                .with_source(why)
+            })
+            .and_then(|mut s| {
+                // the id is the file name, changing it would orphan the old stage
+                s.id = stage.id.clone();
+                check_stage(conf, index, &mut s)?;
+                Ok(s)
+            });
+
+        match edited {
+            Ok(s) => {
+                let _ = fs::remove_file(&path);
+                return Ok(s);
+            }
+            Err(why) => {
+                println!("{}", why);
+                if !casual::confirm("Edit again?") {
+                    let _ = fs::remove_file(&path);
+                    return Err(why);
+                }
+            }
+        }
+    }
+}
+
+fn run_editor(path: &Path) -> Result<(), Error> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or(if cfg!(windows) { "notepad" } else { "vi" }.to_owned());
+
+    // editors like `code --wait` come with their own arguments
+    let mut parts = editor.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| Error::io("The editor set in $VISUAL or $EDITOR is empty."))?;
+
+    let status = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .map_err(|why| {
+            Error::io(format!(
+                "Failed to start editor `{}`, reason: \"{}\".",
+                editor, why
+            ))
+            .with_source(why)
+        })?;
+
+    if !status.success() {
+        return Err(Error::io(format!(
+            "Editor `{}` exited with {}.",
+            editor, status
+        )));
+    }
+
+    Ok(())
+}
+
+fn remove(conf: &Config, ids: Vec<String>, confirm: bool) -> Result<(), Error> {
+    for id in ids {
+        // make sure the stage exists and is readable before asking
+        let stage = StageData::load(&conf.directories, &id)?;
+
+        if !confirm
+            && !casual::confirm(format!(
+                "Remove stage `{}` (\"{}\")?",
+                stage.id, stage.title
+            ))
+        {
+            continue;
+        }
+
+        stage.remove(&conf.directories)?;
+        println!("Removed stage `{}`.", id);
+    }
+
+    Ok(())
+}
+
+fn list(conf: &Config, ids: Option<Vec<String>>) -> Result<(), Error> {
+    match ids {
+        Some(ids) if !ids.is_empty() => {
+            for id in ids {
+                print_stage(&StageData::load(&conf.directories, &id)?);
+            }
+        }
+        _ => {
+            for s in StageData::load_all(&conf.directories)? {
+                println!(
+                    "{}: \"{}\" ({} slices) -- {}",
+                    s.id,
+                    s.title,
+                    s.slices.len(),
+                    s.streamers.join(", ")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_stage(s: &StageData) {
+    println!("Stage `{}`", s.id);
+    println!("  Title: {}", s.title);
+    println!("  Streamers: {}", s.streamers.join(", "));
+    println!("  Description: {}", s.description);
+    if let Some(p) = &s.preset {
+        println!("  Preset: {}", p);
+    }
+    if let Some(t) = &s.thumbnail {
+        println!(
+            "  Thumbnail: \"{}\" heads [{}] game `{}` at slice {} {}",
+            t.text,
+            t.heads.join(", "),
+            t.game,
+            t.video_slice_idx,
+            t.timestamp
+        );
+    }
+    for (i, v) in s.slices.iter().enumerate() {
+        println!("  Slice {}: {} ({} - {})", i, v.video_id, v.ss, v.to);
+    }
+}
+
+fn new_stage_id(conf: &Config) -> String {
+    loop {
+        let id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(6)
+            .map(char::from)
+            .collect::<String>()
+            .to_lowercase();
+        if !StageData::path(&conf.directories, &id).exists() {
+            return id;
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// Stats command, for summarizing what's in the archive and how much space it takes
+
+use crate::config::load_config;
+use crate::error::Error;
+use crate::index::{ArchiveIndex, ContentKind, IndexEntry};
+use crate::util::{format_duration, format_size, print_table};
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KindStats {
+    pub count: usize,
+    pub duration: usize,
+    pub size: usize,
+    pub chat_messages: usize,
+}
+impl KindStats {
+    fn add(&mut self, e: &IndexEntry) {
+        self.count += 1;
+        self.duration += e.meta_usize("duration");
+        self.size += e.size;
+        self.chat_messages += e.meta_usize("messageCount");
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LargestItem {
+    pub kind: ContentKind,
+    pub id: String,
+    pub streamer: String,
+    pub size: usize,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveStats {
+    // channel -> kind -> stats, sorted so output is stable between runs
+    pub channels: BTreeMap<String, BTreeMap<String, KindStats>>,
+    pub totals: BTreeMap<String, KindStats>,
+    // month the content was created in (YYYY-MM) -> stats
+    pub growth: BTreeMap<String, KindStats>,
+    pub largest: Vec<LargestItem>,
+}
+
+pub fn run(config_path: PathBuf, json: bool, top: usize) -> Result<(), Error> {
+    let conf = load_config(&config_path)?;
+    let index = ArchiveIndex::load(&conf)?;
+
+    let stats = collect(&index, top);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        return Ok(());
+    }
+
+    let row = |name: &str, kind: &str, s: &KindStats| {
+        vec![
+            name.to_owned(),
+            kind.to_owned(),
+            s.count.to_string(),
+            format_duration(s.duration),
+            format_size(s.size, 1, true),
+            s.chat_messages.to_string(),
+        ]
+    };
+    let headers = ["CHANNEL", "KIND", "COUNT", "LENGTH", "SIZE", "MESSAGES"];
+
+    let mut rows = Vec::new();
+    for (channel, kinds) in &stats.channels {
+        for (kind, s) in kinds {
+            rows.push(row(channel, kind, s));
+        }
+    }
+    for (kind, s) in &stats.totals {
+        rows.push(row("(total)", kind, s));
+    }
+    print_table(&headers, &rows);
+    println!();
+
+    let mut cumulative = 0;
+    let rows: Vec<_> = stats
+        .growth
+        .iter()
+        .map(|(month, s)| {
+            cumulative += s.size;
+            vec![
+                month.clone(),
+                s.count.to_string(),
+                format_size(s.size, 1, true),
+                format_size(cumulative, 1, true),
+            ]
+        })
+        .collect();
+    print_table(&["MONTH", "ITEMS", "ADDED", "CUMULATIVE"], &rows);
+    println!();
+
+    let rows: Vec<_> = stats
+        .largest
+        .iter()
+        .map(|f| {
+            vec![
+                f.kind.noun().to_owned(),
+                f.id.clone(),
+                f.streamer.clone(),
+                format_size(f.size, 1, true),
+                f.path.display().to_string(),
+            ]
+        })
+        .collect();
+    print_table(&["KIND", "ID", "STREAMER", "SIZE", "PATH"], &rows);
+
+    Ok(())
+}
+
+pub fn collect(index: &ArchiveIndex, top: usize) -> ArchiveStats {
+    let mut stats = ArchiveStats::default();
+
+    for e in index.entries() {
+        let kind = e.kind.noun().to_owned();
+        stats
+            .channels
+            .entry(e.streamer.clone())
+            .or_default()
+            .entry(kind.clone())
+            .or_default()
+            .add(e);
+        stats.totals.entry(kind).or_default().add(e);
+
+        let created_at = e.meta_str("createdAt");
+        let month = created_at.get(..7).unwrap_or("unknown").to_owned();
+        stats.growth.entry(month).or_default().add(e);
+    }
+
+    let mut largest: Vec<_> = index.entries().collect();
+    largest.sort_by_key(|e| std::cmp::Reverse(e.size));
+    stats.largest = largest
+        .into_iter()
+        .take(top)
+        .map(|e| LargestItem {
+            kind: e.kind,
+            id: e.id.clone(),
+            streamer: e.streamer.clone(),
+            size: e.size,
+            path: e.path.clone(),
+        })
+        .collect();
+
+    stats
+}
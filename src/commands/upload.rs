@@ -0,0 +1,142 @@
+// Upload command, for exporting a stage and uploading it to YouTube
+
+use crate::chat;
+use crate::commands::export::{export_stage, select_preset};
+use crate::config::{load_config, ChatExportFormat};
+use crate::description;
+use crate::error::Error;
+use crate::index::ArchiveIndex;
+use crate::oauth;
+use crate::progress::{DownloadProgress, ProgressObserver};
+use crate::thumbnail;
+use crate::util::format_size;
+use crate::vodbot_api::StageData;
+use crate::youtube::{VideoMetadata, YouTubeClient};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use std::path::PathBuf;
+
+pub fn run(config_path: PathBuf, stage_id: String, preset: Option<String>) -> Result<(), Error> {
+    let conf = load_config(&config_path)?;
+    let stage = StageData::load(&conf.directories, &stage_id)?;
+    let preset = select_preset(&conf, &stage, preset.as_deref())?;
+    // fail before the long export if there's no login at all, the token
+    // itself is only checked once the export is done
+    oauth::YouTubeSession::load(&conf.upload.session_path)?;
+
+    // render the text up front, so a bad template fails before the long export
+    let index = ArchiveIndex::load(&conf)?;
+    let (title, description) = description::render_stage(&conf, &index, &stage)?;
+
+    let video_path = conf.directories.temp.join(format!("{}.mkv", stage.id));
+    let state_path = conf
+        .directories
+        .stage
+        .join(format!("{}.upload.json", stage.id));
+
+    // an interrupted upload already has its export, no need to redo it
+    if state_path.exists() && video_path.exists() {
+        println!(
+            "Found previous upload of stage `{}`, resuming ...",
+            stage.id
+        );
+    } else {
+        println!("Exporting stage `{}` ...", stage.id);
+        export_stage(&conf, &stage, preset, &video_path)?;
+    }
+
+    println!("Uploading stage `{}` ...", stage.id);
+    let session = oauth::authorize(&conf)?;
+    let client = YouTubeClient::new(&conf, session.access_token);
+    let meta = VideoMetadata::new(&title, &description, conf.upload.privacy);
+    let video_id = client.upload_video(
+        &video_path,
+        &meta,
+        conf.upload.chunk_size,
+        conf.upload.notify_subscribers,
+        &state_path,
+        &UploadBar::new(),
+    )?;
+    println!(
+        "Uploaded stage `{}` to https://youtu.be/{}",
+        stage.id, video_id
+    );
+
+    if conf.upload.chat_enable {
+        if conf.chat.export_format == ChatExportFormat::Raw {
+            log::warn!("raw chat export can't be used as captions, skipping chat upload");
+        } else {
+            let messages = chat::stage_chat(&index, &stage)?;
+            if messages.is_empty() {
+                println!("No chat to attach to the video.");
+            } else {
+                println!("Attaching {} chat messages as captions ...", messages.len());
+                client.insert_caption(
+                    &video_id,
+                    &conf.upload.chat_track_name,
+                    &conf.upload.chat_track_language,
+                    chat::render(&conf.chat, &messages).into_bytes(),
+                )?;
+            }
+        }
+    }
+
+    if conf.upload.thumbnail_enable && stage.thumbnail.is_some() {
+        println!("Setting thumbnail ...");
+        let thumbnail_path = video_path.with_extension("png");
+        thumbnail::render(&conf, &stage, &thumbnail_path)?;
+        let result = client.set_thumbnail(&video_id, &thumbnail_path);
+        let _ = std::fs::remove_file(&thumbnail_path);
+        result?;
+    }
+
+    let _ = std::fs::remove_file(&video_path);
+    if conf.stage.delete_on_upload {
+        stage.remove(&conf.directories)?;
+    }
+
+    Ok(())
+}
+
+// A progress bar for the upload, hidden when stderr isn't a terminal
+struct UploadBar {
+    bar: ProgressBar,
+}
+impl UploadBar {
+    fn new() -> Self {
+        let bar = ProgressBar::new(1);
+        bar.set_style(
+            ProgressStyle::with_template("{prefix} [{bar:30}] {percent:>3}% -- {msg}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        Self { bar }
+    }
+}
+impl ProgressObserver for UploadBar {
+    fn started(&self, noun: &str, id: &str) {
+        self.bar.set_prefix(format!("{} `{}`", noun, id));
+    }
+
+    fn progress(&self, p: &DownloadProgress) {
+        self.bar.set_length(p.estimated_bytes as u64);
+        self.bar.set_position(p.bytes as u64);
+        self.bar.set_message(format!(
+            "{} of {} (@ {}/s) -- ({:.0}s left)",
+            format_size(p.bytes, 1, true),
+            format_size(p.estimated_bytes, 1, true),
+            format_size(p.bytes_per_second, 1, true),
+            p.seconds_left
+        ));
+    }
+
+    fn finished(&self, id: &str, bytes: usize, seconds: f32) {
+        self.bar.finish_and_clear();
+        println!(
+            "Upload `{}` -- {: >8} in {:.1} seconds",
+            id,
+            format_size(bytes, 1, true),
+            seconds
+        );
+    }
+}
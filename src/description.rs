@@ -0,0 +1,232 @@
+// Stage text templating, for building upload titles and descriptions out of
+// what's known about the staged videos.
+//
+// Built in placeholders:
+//   {title}           the rendered stage title (only in descriptions)
+//   {streamers}       streamer names, comma separated
+//   {streamer_links}  a Twitch channel link per streamer, one per line
+//   {date}            date of the first video in `stage.timezone`, takes a strftime spec
+//   {games}           games played across the slices, comma separated
+//   {chapters}        YouTube chapter list with timestamps in the final video
+//   {vod_links}       a Twitch link per slice, starting where the slice starts
+// Anything else comes from `stage.description_macros`, as `name=value`. Macro
+// values can use the built in placeholders, except {title}, but not other macros.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::index::{ArchiveIndex, ContentKind};
+use crate::template;
+use crate::timestamp;
+use crate::vodbot_api::StageData;
+
+const DEFAULT_DATE_FORMAT: &str = "%B %-d, %Y";
+
+#[derive(Debug, Clone)]
+struct Chapter {
+    start: usize,
+    name: String,
+}
+
+// Everything placeholders can refer to, gathered once per stage
+struct StageContext {
+    streamers: Vec<String>,
+    created_at: Option<DateTime<FixedOffset>>,
+    games: Vec<String>,
+    chapters: Vec<Chapter>,
+    links: Vec<String>,
+}
+
+// Render a stage's title and description, returned in that order
+pub fn render_stage(
+    conf: &Config,
+    index: &ArchiveIndex,
+    stage: &StageData,
+) -> Result<(String, String), Error> {
+    let ctx = StageContext::new(conf, index, stage)?;
+
+    let macros = parse_macros(&conf.stage.description_macros)?
+        .into_iter()
+        .map(|(k, v)| template::render(&v, |n, s| ctx.lookup(n, s)).map(|v| (k, v)))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    let lookup = |name: &str, spec: Option<&str>| {
+        ctx.lookup(name, spec)
+            .or_else(|| macros.get(name).filter(|_| spec.is_none()).cloned())
+    };
+
+    let title = template::render(&stage.title, lookup)?;
+    let description = template::render(&stage.description, |n, s| match (n, s) {
+        ("title", None) => Some(title.clone()),
+        _ => lookup(n, s),
+    })?;
+
+    Ok((title, description))
+}
+
+// Timezones are offsets like `+0000`, as validated by the config
+pub fn parse_timezone(s: &str) -> Option<FixedOffset> {
+    let sign = match s.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let hours: i32 = s.get(1..3)?.parse().ok()?;
+    let minutes: i32 = s.get(3..5)?.parse().ok()?;
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+fn parse_macros(macros: &[String]) -> Result<HashMap<String, String>, Error> {
+    macros
+        .iter()
+        .map(|m| match m.split_once('=') {
+            Some((k, v)) if !k.trim().is_empty() => Ok((k.trim().to_owned(), v.to_owned())),
+            _ => Err(Error::input(format!(
+                "Description macro `{}` should look like `name=value`.",
+                m
+            ))),
+        })
+        .collect()
+}
+
+impl StageContext {
+    fn new(conf: &Config, index: &ArchiveIndex, stage: &StageData) -> Result<Self, Error> {
+        let tz = parse_timezone(&conf.stage.timezone).ok_or_else(|| {
+            Error::input(format!(
+                "Invalid timezone `{}`, expected an offset like `+0000`.",
+                conf.stage.timezone
+            ))
+        })?;
+
+        let mut ctx = Self {
+            streamers: stage.streamers.clone(),
+            created_at: None,
+            games: Vec::new(),
+            chapters: Vec::new(),
+            links: Vec::new(),
+        };
+        let mut slice_start = 0;
+
+        for s in &stage.slices {
+            let ss = timestamp::slice_time(&s.ss)?;
+            let to = timestamp::slice_time(&s.to)?;
+
+            let e = match index.find_video(&s.video_id) {
+                Some(e) => e,
+                None => {
+                    log::warn!("video {} is no longer archived, leaving it out", s.video_id);
+                    slice_start += to.saturating_sub(ss);
+                    continue;
+                }
+            };
+
+            if ctx.created_at.is_none() {
+                ctx.created_at = DateTime::parse_from_rfc3339(&e.meta_str("createdAt"))
+                    .ok()
+                    .map(|f| f.with_timezone(&tz));
+            }
+
+            // vod chapters that overlap the slice, moved to where the slice
+            // lands in the final video
+            let mut chapters: Vec<Chapter> = e
+                .chapters()
+                .iter()
+                .filter(|c| c.position < to && c.position + c.duration > ss)
+                .map(|c| Chapter {
+                    start: c.position.max(ss) - ss + slice_start,
+                    name: c.description.clone(),
+                })
+                .collect();
+            if chapters.is_empty() {
+                chapters.push(Chapter {
+                    start: slice_start,
+                    name: e.meta_str("gameName"),
+                });
+            }
+
+            for c in chapters {
+                if !c.name.is_empty() && !ctx.games.contains(&c.name) {
+                    ctx.games.push(c.name.clone());
+                }
+                // back to back slices of the same game stay one chapter
+                if ctx.chapters.last().is_some_and(|l| l.name == c.name) {
+                    continue;
+                }
+                ctx.chapters.push(c);
+            }
+
+            ctx.links.push(match e.kind {
+                ContentKind::Clip => format!("https://clips.twitch.tv/{}", e.id),
+                _ => format!(
+                    "https://www.twitch.tv/videos/{}?t={}",
+                    e.id,
+                    twitch_time(ss)
+                ),
+            });
+
+            slice_start += to.saturating_sub(ss);
+        }
+
+        Ok(ctx)
+    }
+
+    fn lookup(&self, name: &str, spec: Option<&str>) -> Option<String> {
+        if spec.is_some() && name != "date" {
+            return None;
+        }
+
+        match name {
+            "streamers" => Some(self.streamers.join(", ")),
+            "streamer_links" => Some(
+                self.streamers
+                    .iter()
+                    .map(|f| format!("https://www.twitch.tv/{}", f))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            "date" => match &self.created_at {
+                Some(d) => template::format_timestamp(
+                    &d.to_rfc3339(),
+                    Some(spec.unwrap_or(DEFAULT_DATE_FORMAT)),
+                ),
+                None => Some(String::new()),
+            },
+            "games" => Some(self.games.join(", ")),
+            "chapters" => Some(
+                self.chapters
+                    .iter()
+                    .map(|c| format!("{} {}", chapter_time(c.start), c.name))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            "vod_links" => Some(self.links.join("\n")),
+            _ => None,
+        }
+    }
+}
+
+// YouTube wants `M:SS` until the video is an hour long
+fn chapter_time(seconds: usize) -> String {
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            (seconds / 60) % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn twitch_time(seconds: usize) -> String {
+    format!(
+        "{}h{}m{}s",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,90 @@
+// Library errors, sorted by what went wrong so callers can tell a video that
+// isn't there from a dropped connection without reading the message. Each one
+// has a message for people and, where there is one, the error that caused it.
+// Which exit code goes with which error is up to the binary.
+
+use std::fmt;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+macro_rules! error_kinds {
+    ($($variant:ident => $ctor:ident,)*) => {
+        #[derive(Debug)]
+        pub enum Error {
+            $($variant { msg: String, source: Option<BoxError> },)*
+        }
+        impl Error {
+            $(
+                pub fn $ctor(msg: impl Into<String>) -> Self {
+                    Error::$variant {
+                        msg: msg.into(),
+                        source: None,
+                    }
+                }
+            )*
+
+            fn parts(&self) -> (&String, &Option<BoxError>) {
+                match self {
+                    $(Error::$variant { msg, source } => (msg, source),)*
+                }
+            }
+
+            fn parts_mut(&mut self) -> (&mut String, &mut Option<BoxError>) {
+                match self {
+                    $(Error::$variant { msg, source } => (msg, source),)*
+                }
+            }
+        }
+    };
+}
+
+error_kinds! {
+    // The config can't be loaded, or has values that can't be used
+    Config => config,
+    // Reading or writing files and directories, or running other programs
+    Io => io,
+    // Twitch, YouTube or a CDN couldn't be reached, or answered with an error
+    Network => network,
+    // Twitch answered a GQL query with errors of its own
+    Gql => gql,
+    // Twitch or the archive doesn't have what was asked for
+    NotFound => not_found,
+    // Something from Twitch, YouTube or on disk isn't shaped how we expect
+    Parse => parse,
+    // FFMPEG couldn't start, failed or was interrupted
+    Ffmpeg => ffmpeg,
+    // Bad arguments, stage data, templates or timestamps
+    Input => input,
+    // Not logged in to YouTube, or the login was refused
+    Auth => auth,
+    // Threads and channels inside VodBot itself
+    Internal => internal,
+}
+
+impl Error {
+    pub fn with_source(mut self, source: impl Into<BoxError>) -> Self {
+        *self.parts_mut().1 = Some(source.into());
+        self
+    }
+
+    pub fn msg(&self) -> &str {
+        self.parts().0
+    }
+
+    pub fn msg_mut(&mut self) -> &mut String {
+        self.parts_mut().0
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.msg())
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.parts()
+            .1
+            .as_ref()
+            .map(|s| s.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
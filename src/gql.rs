@@ -1,90 +1,386 @@
 // GQL Client, for making GQL calls to Twitch's backend.
 
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, GQLMode};
+use crate::error::Error;
+use crate::http::{HttpError, HttpRequest, HttpTransport, ReqwestTransport};
 use crate::twitch_api;
 use crate::twitch_api::TwitchResponse;
 use crate::util;
 
 use rand::{distributions::Alphanumeric, Rng};
-use reqwest::blocking::Client;
-use reqwest::blocking::Response;
-use serde::Serialize;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// GQL errors that go away if the query is sent again a bit later
+const TRANSIENT_ERRORS: &[&str] = &["service timeout", "service unavailable", "service error"];
 
 #[derive(Serialize)]
 struct GQLQuery {
     query: String,
 }
 
+// A recorded query and Twitch's answer to it, named after a hash of the query
+#[derive(Serialize, Deserialize)]
+struct GQLFixture {
+    query: String,
+    response: String,
+}
+
 pub struct GQLClient {
-    client_id: String,
-    device_id: String,
-    url: String,
-    client: Client,
+    state: GQLState,
+    transport: Arc<dyn HttpTransport>,
 }
 impl GQLClient {
-    pub fn new(client_id: String) -> GQLClient {
-        GQLClient {
-            client_id: client_id,
-            device_id: rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(32)
-                .map(char::from)
-                .collect(),
-            url: String::from("https://gql.twitch.tv/gql"),
-            client: Client::new(),
+    pub fn new(conf: &Config) -> Result<GQLClient, Error> {
+        Self::with_transport(conf, Arc::new(ReqwestTransport::new(conf)?))
+    }
+
+    pub fn with_transport(
+        conf: &Config,
+        transport: Arc<dyn HttpTransport>,
+    ) -> Result<GQLClient, Error> {
+        Ok(GQLClient {
+            state: GQLState::new(conf)?,
+            transport,
+        })
+    }
+
+    // The transport queries go through, for sharing with downloads
+    pub fn transport(&self) -> Arc<dyn HttpTransport> {
+        self.transport.clone()
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.state.batch_size
+    }
+
+    pub fn parallel_batches(&self) -> usize {
+        self.state.parallel_batches
+    }
+
+    pub fn raw_query(&self, query: String) -> Result<String, Error> {
+        if self.state.mode == GQLMode::Replay {
+            let path = self.state.fixture_path(&query);
+            return self.state.replay(&query, &path, fs::read_to_string(&path));
         }
+
+        let text = self.send(&query)?;
+
+        if self.state.mode == GQLMode::Record {
+            util::create_dir(&self.state.fixtures)?;
+            let (path, fixture) = self.state.record(&query, &text);
+            fs::write(&path, fixture).map_err(|why| fixture_error(&path, why))?;
+        }
+
+        Ok(text)
     }
 
-    pub fn raw_query(&self, query: String) -> Result<Response, util::ExitMsg> {
-        let resp = self
-            .client
-            .post(&self.url)
-            .header("Client-ID", &self.client_id)
-            .header("X-Device-ID", &self.device_id)
-            .json(&GQLQuery { query: query })
-            .send()
-            .map_err(|why| {
-                util::ExitMsg::new(
-                    util::ExitCode::CannotConnectToTwitch,
-                    format!("Cannot connect to Twitch, reason: \"{}\".", why),
-                )
-            })?;
+    pub fn query<T>(&self, query: String) -> Result<TwitchResponse<T>, Error>
+    where
+        T: twitch_api::TwitchData + for<'de> serde::Deserialize<'de>,
+    {
+        let mut tries = 0;
+        loop {
+            let s = self.raw_query(query.clone())?;
+            if let Some(j) = self.state.answer(&query, &s, &mut tries)? {
+                return Ok(j);
+            }
+        }
+    }
 
-        if !resp.status().is_success() {
-            return Err(util::ExitMsg::new(
-                util::ExitCode::RequestErrorFromTwitch,
-                format!(
-                    "Error response from Twitch GQL: \"{}\".",
-                    resp.text().unwrap()
-                ),
-            ));
+    // Post a query, waiting for our turn under the rate limit and retrying
+    // when Twitch throttles us or has trouble of its own
+    fn send(&self, query: &str) -> Result<String, Error> {
+        let mut tries = 0;
+        loop {
+            thread::sleep(
+                self.state
+                    .next_turn()
+                    .saturating_duration_since(Instant::now()),
+            );
+
+            let (err, wait) = match self.transport.send(self.state.request(query)) {
+                Ok(r) => match self.state.reply(r.status, &r.headers, tries) {
+                    Reply::Success => return r.text().map_err(read_error),
+                    Reply::Retry(wait) => (status_error(r.status), wait),
+                    Reply::Fail => return Err(body_error(&r.text().unwrap_or_default())),
+                },
+                Err(why) => (connect_error(why), backoff(tries + 1)),
+            };
+
+            self.state.retry(&mut tries, err, wait)?;
         }
+    }
+}
 
-        Ok(resp)
+// Everything about sending queries that doesn't care how they get sent: the
+// headers, rate limiting, when to retry and the fixtures. Shared with the
+// async client in `nonblocking`.
+pub(crate) struct GQLState {
+    // Client-ID, X-Device-ID and the OAuth token if there is one, sent with
+    // every query
+    headers: HeaderMap,
+    url: String,
+    pub(crate) mode: GQLMode,
+    pub(crate) fixtures: PathBuf,
+    pub(crate) batch_size: usize,
+    pub(crate) parallel_batches: usize,
+    retries: usize,
+    // Requests are spaced out by `interval`, `next_request` is when the next
+    // one may go out
+    interval: Duration,
+    next_request: Mutex<Instant>,
+}
+
+// What to do about Twitch's answer to a query
+pub(crate) enum Reply {
+    Success,
+    // Throttled or a server error, try again after waiting a while
+    Retry(Duration),
+    Fail,
+}
+
+impl GQLState {
+    pub(crate) fn new(conf: &Config) -> Result<Self, Error> {
+        let device_id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let client_id = HeaderValue::from_str(&conf.pull.gql_client_id).map_err(|why| {
+            Error::config(format!("Invalid GQL client id, reason: \"{}\".", why)).with_source(why)
+        })?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(HeaderName::from_static("client-id"), client_id);
+        headers.insert(
+            HeaderName::from_static("x-device-id"),
+            HeaderValue::from_str(&device_id).unwrap(),
+        );
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        if let Some(o) = oauth_header(conf)? {
+            headers.insert(AUTHORIZATION, o);
+        }
+
+        Ok(Self {
+            headers,
+            url: conf.pull.gql_url.clone(),
+            mode: conf.pull.gql_mode,
+            fixtures: conf.pull.gql_fixtures.clone(),
+            batch_size: conf.pull.gql_batch_size.max(1),
+            parallel_batches: conf.pull.gql_parallel_batches.max(1),
+            retries: conf.pull.connection_retries,
+            interval: Duration::from_secs_f64(1.0 / conf.pull.gql_requests_per_second),
+            next_request: Mutex::new(Instant::now()),
+        })
     }
 
-    pub fn query<T>(&self, query: String) -> Result<TwitchResponse<T>, util::ExitMsg>
+    pub(crate) fn request(&self, query: &str) -> HttpRequest {
+        let body = serde_json::to_vec(&GQLQuery {
+            query: query.to_owned(),
+        })
+        .unwrap();
+        let mut req = HttpRequest::post(&self.url, body);
+        req.headers = self.headers.clone();
+        req
+    }
+
+    // Claim the next turn under the rate limit, the request should wait
+    // until the returned instant before going out
+    pub(crate) fn next_turn(&self) -> Instant {
+        let mut next = self.next_request.lock().unwrap();
+        let at = (*next).max(Instant::now());
+        *next = at + self.interval;
+        at
+    }
+
+    // Hold back every request, not only the one that got throttled
+    fn hold_off(&self, wait: Duration) {
+        let mut next = self.next_request.lock().unwrap();
+        *next = (*next).max(Instant::now() + wait);
+    }
+
+    pub(crate) fn reply(&self, status: StatusCode, headers: &HeaderMap, tries: usize) -> Reply {
+        if status.is_success() {
+            Reply::Success
+        } else if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            Reply::Retry(retry_after(headers).unwrap_or_else(|| backoff(tries + 1)))
+        } else {
+            Reply::Fail
+        }
+    }
+
+    // Count a failed try, giving up with `err` once there have been too many
+    pub(crate) fn retry(&self, tries: &mut usize, err: Error, wait: Duration) -> Result<(), Error> {
+        *tries += 1;
+        if *tries > self.retries {
+            return Err(err);
+        }
+        log::warn!("{} Retrying in {}s.", err, wait.as_secs());
+        self.hold_off(wait);
+        Ok(())
+    }
+
+    // Parse the response to a query. None means Twitch had a passing problem
+    // and the query should be sent again.
+    pub(crate) fn answer<T>(
+        &self,
+        query: &str,
+        response: &str,
+        tries: &mut usize,
+    ) -> Result<Option<TwitchResponse<T>>, Error>
     where
         T: twitch_api::TwitchData + for<'de> serde::Deserialize<'de>,
     {
-        let s = self.raw_query(query.clone())?.text().unwrap();
-        let j: TwitchResponse<T> = serde_json::from_str(&s).map_err(|why| util::ExitMsg::new(
-            util::ExitCode::CannotParseResponseFromTwitch,
-            format!(
+        let j: TwitchResponse<T> = serde_json::from_str(response).map_err(|why| {
+            Error::parse(format!(
                 "Failed to parse response from Twitch, reason: \"{}\".\nQuery: `{}`\nResponse: `{}`",
-                why, query, s
-            ),
-        ))?;
-
-        if let Some(errors) = j.errors {
-            return Err(util::ExitMsg::new(
-                util::ExitCode::GQLErrorFromTwitch,
-                format!(
-                    "Something went wrong with the GQL request: \"{:?}\".",
-                    errors
-                ),
-            ));
+                why, query, response
+            ))
+            .with_source(why)
+        })?;
+
+        let errors = match &j.errors {
+            None => return Ok(Some(j)),
+            Some(e) => e,
+        };
+
+        let transient = errors.iter().all(|e| {
+            let msg = e.message.to_lowercase();
+            TRANSIENT_ERRORS.iter().any(|t| msg.contains(t))
+        });
+        // a replayed answer never changes, so there's no point asking again
+        if !transient || self.mode == GQLMode::Replay || *tries >= self.retries {
+            return Err(Error::gql(format!(
+                "Something went wrong with the GQL request: \"{:?}\".",
+                errors
+            )));
         }
 
-        Ok(j)
+        *tries += 1;
+        let wait = backoff(*tries);
+        log::warn!(
+            "transient GQL error from Twitch, retrying in {}s: {:?}",
+            wait.as_secs(),
+            errors
+        );
+        self.hold_off(wait);
+        Ok(None)
     }
+
+    pub(crate) fn fixture_path(&self, query: &str) -> PathBuf {
+        let hash = Sha256::digest(query.as_bytes());
+        self.fixtures.join(format!("{:x}.json", hash))
+    }
+
+    // Where to record a query and its answer, and what to write there
+    pub(crate) fn record(&self, query: &str, response: &str) -> (PathBuf, String) {
+        let path = self.fixture_path(query);
+        let fixture = GQLFixture {
+            query: query.to_owned(),
+            response: response.to_owned(),
+        };
+
+        log::debug!("recording gql fixture {}", path.display());
+        (path, serde_json::to_string_pretty(&fixture).unwrap())
+    }
+
+    // The answer out of a fixture read from `path`
+    pub(crate) fn replay(
+        &self,
+        query: &str,
+        path: &Path,
+        data: std::io::Result<String>,
+    ) -> Result<String, Error> {
+        log::debug!("replaying gql fixture {}", path.display());
+
+        let fixture: GQLFixture = data
+            .map_err(|why| why.to_string())
+            .and_then(|f| serde_json::from_str(&f).map_err(|why| why.to_string()))
+            .map_err(|why| {
+                Error::io(format!(
+                    "Failed to read GQL fixture `{}`, reason: \"{}\".\nQuery: `{}`",
+                    path.display(),
+                    why,
+                    query
+                ))
+                .with_source(why)
+            })?;
+
+        Ok(fixture.response)
+    }
+}
+
+pub(crate) fn fixture_error(path: &Path, why: std::io::Error) -> Error {
+    Error::io(format!(
+        "Failed to write GQL fixture `{}`, reason: \"{}\".",
+        path.display(),
+        why
+    ))
+    .with_source(why)
+}
+
+pub(crate) fn read_error(why: HttpError) -> Error {
+    Error::network(format!(
+        "Cannot read response from Twitch, reason: \"{}\".",
+        why
+    ))
+    .with_source(why)
+}
+
+pub(crate) fn status_error(status: StatusCode) -> Error {
+    Error::network(format!("Error response from Twitch GQL: \"{}\".", status))
+}
+
+pub(crate) fn body_error(body: &str) -> Error {
+    Error::network(format!("Error response from Twitch GQL: \"{}\".", body))
+}
+
+pub(crate) fn connect_error(why: HttpError) -> Error {
+    Error::network(format!("Cannot connect to Twitch, reason: \"{}\".", why)).with_source(why)
+}
+
+// The `Authorization` header for the configured OAuth token, shared with usher
+// requests so sub only vods can be downloaded too
+pub fn oauth_header(conf: &Config) -> Result<Option<HeaderValue>, Error> {
+    let token = match conf.pull.oauth_token()? {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+
+    let mut header = HeaderValue::from_str(&format!("OAuth {}", token)).map_err(|_| {
+        Error::config("Twitch OAuth token has characters that can't be sent in a header.")
+    })?;
+    header.set_sensitive(true);
+    Ok(Some(header))
+}
+
+pub(crate) fn backoff(tries: usize) -> Duration {
+    Duration::from_secs(1 << tries.min(6))
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(s) = value.parse::<u64>() {
+        return Some(Duration::from_secs(s));
+    }
+
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // a date that's already passed means go ahead now
+    Some(
+        (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
 }
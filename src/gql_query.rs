@@ -0,0 +1,158 @@
+// GQL query builder. Queries are built out of fields instead of formatted
+// strings, so logins, ids and cursors are always escaped, and what each
+// `twitch_api` struct asks for is written once next to the struct itself.
+
+use std::fmt;
+
+// An argument value. Strings are quoted and escaped, enums are written bare.
+#[derive(Debug, Clone)]
+pub enum Arg {
+    String(String),
+    Int(usize),
+    Enum(String),
+    List(Vec<Arg>),
+    Object(Vec<(&'static str, Arg)>),
+}
+impl From<&str> for Arg {
+    fn from(s: &str) -> Self {
+        Arg::String(s.to_owned())
+    }
+}
+impl From<String> for Arg {
+    fn from(s: String) -> Self {
+        Arg::String(s)
+    }
+}
+impl From<usize> for Arg {
+    fn from(i: usize) -> Self {
+        Arg::Int(i)
+    }
+}
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arg::String(s) => f.write_str(&escape(s)),
+            Arg::Int(i) => write!(f, "{}", i),
+            // enum values can't be quoted, so anything that isn't a name
+            // character is dropped instead
+            Arg::Enum(e) => f.write_str(
+                &e.chars()
+                    .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+                    .collect::<String>(),
+            ),
+            Arg::List(l) => {
+                let l: Vec<_> = l.iter().map(|a| a.to_string()).collect();
+                write!(f, "[{}]", l.join(", "))
+            }
+            Arg::Object(o) => {
+                let o: Vec<_> = o.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                write!(f, "{{{}}}", o.join(", "))
+            }
+        }
+    }
+}
+
+// Quote a string as a GQL string value
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// A field and what's selected inside it. Field and argument names are
+// `'static` so they only ever come from the code, never from user input.
+#[derive(Debug, Clone)]
+pub struct Field {
+    alias: Option<String>,
+    name: &'static str,
+    args: Vec<(&'static str, Arg)>,
+    selection: Vec<Field>,
+}
+impl Field {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            alias: None,
+            name,
+            args: Vec::new(),
+            selection: Vec::new(),
+        }
+    }
+
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.alias = Some(alias.to_owned());
+        self
+    }
+
+    pub fn arg(mut self, name: &'static str, value: impl Into<Arg>) -> Self {
+        self.args.push((name, value.into()));
+        self
+    }
+
+    pub fn select(mut self, fields: Vec<Field>) -> Self {
+        self.selection.extend(fields);
+        self
+    }
+
+    // Select everything `S` reads
+    pub fn of<S: Selection>(self) -> Self {
+        self.select(S::selection())
+    }
+}
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(a) = &self.alias {
+            write!(f, "{}: ", a)?;
+        }
+        f.write_str(self.name)?;
+        if !self.args.is_empty() {
+            let args: Vec<_> = self
+                .args
+                .iter()
+                .map(|(k, v)| format!("{}: {}", k, v))
+                .collect();
+            write!(f, "({})", args.join(", "))?;
+        }
+        if !self.selection.is_empty() {
+            write!(f, " {{ {} }}", join(&self.selection))?;
+        }
+        Ok(())
+    }
+}
+
+// Fields with nothing selected inside them, like `id` or `title`
+pub fn fields(names: &[&'static str]) -> Vec<Field> {
+    names.iter().map(|n| Field::new(n)).collect()
+}
+
+// A whole query document out of its top level fields
+pub fn document(fields: &[Field]) -> String {
+    format!("{{ {} }}", join(fields))
+}
+
+fn join(fields: &[Field]) -> String {
+    fields
+        .iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The fields a type is deserialized from. Nested types select through their
+// own implementation, so each field is only listed in one place.
+pub trait Selection {
+    fn selection() -> Vec<Field>;
+}
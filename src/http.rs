@@ -0,0 +1,268 @@
+// HTTP transport, what GQL queries and downloads are sent through. The default
+// is a reqwest client set up from the config, but anything that can answer a
+// request can stand in for it, like a client behind some special proxy or a
+// fake that never touches the network.
+
+use std::fs;
+use std::io::Read;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Method, Proxy, StatusCode};
+
+use crate::config::Config;
+use crate::error::Error;
+
+#[cfg(feature = "async")]
+use futures_util::future::BoxFuture;
+#[cfg(feature = "async")]
+use futures_util::stream::{self, BoxStream, StreamExt};
+
+pub type HttpError = Box<dyn std::error::Error + Send + Sync>;
+
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Option<Vec<u8>>,
+    pub timeout: Option<Duration>,
+}
+impl HttpRequest {
+    pub fn get(url: &str) -> Self {
+        Self {
+            method: Method::GET,
+            url: url.to_owned(),
+            headers: HeaderMap::new(),
+            body: None,
+            timeout: None,
+        }
+    }
+
+    pub fn post(url: &str, body: Vec<u8>) -> Self {
+        Self {
+            method: Method::POST,
+            body: Some(body),
+            ..Self::get(url)
+        }
+    }
+
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    // Read as it arrives, so big downloads don't have to sit in memory
+    pub body: Box<dyn Read + Send>,
+}
+impl HttpResponse {
+    pub fn bytes(mut self) -> Result<Vec<u8>, HttpError> {
+        let mut buf = Vec::new();
+        self.body.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn text(self) -> Result<String, HttpError> {
+        Ok(String::from_utf8(self.bytes()?)?)
+    }
+}
+
+pub trait HttpTransport: Send + Sync {
+    fn send(&self, req: HttpRequest) -> Result<HttpResponse, HttpError>;
+}
+
+pub struct ReqwestTransport {
+    client: Client,
+}
+impl ReqwestTransport {
+    // A client with the proxy, TLS roots, user agent and pooling from the config
+    pub fn new(conf: &Config) -> Result<Self, Error> {
+        let http = &conf.pull.http;
+        let mut builder = Client::builder()
+            .pool_max_idle_per_host(http.pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_secs(http.pool_idle_timeout));
+
+        if let Some(ua) = &http.user_agent {
+            builder = builder.user_agent(ua);
+        }
+        if let Some(proxy) = proxy(conf)? {
+            builder = builder.proxy(proxy);
+        }
+        for cert in ca_certs(conf)? {
+            builder = builder.add_root_certificate(cert);
+        }
+
+        let client = builder.build().map_err(client_error)?;
+        Ok(Self { client })
+    }
+
+    // For embedders that already have a reqwest client set up how they like
+    pub fn from_client(client: Client) -> Self {
+        Self { client }
+    }
+}
+impl HttpTransport for ReqwestTransport {
+    fn send(&self, req: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let mut builder = self
+            .client
+            .request(req.method, &req.url)
+            .headers(req.headers);
+        if let Some(b) = req.body {
+            builder = builder.body(b);
+        }
+        if let Some(t) = req.timeout {
+            builder = builder.timeout(t);
+        }
+
+        let resp = builder.send()?;
+        Ok(HttpResponse {
+            status: resp.status(),
+            headers: resp.headers().clone(),
+            body: Box::new(resp),
+        })
+    }
+}
+
+// The async side of the transport, for `nonblocking`. Bodies come in as a
+// stream of chunks, so downloads can be written out as they arrive.
+#[cfg(feature = "async")]
+pub type HttpBody = BoxStream<'static, Result<Vec<u8>, HttpError>>;
+
+#[cfg(feature = "async")]
+pub struct AsyncHttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: HttpBody,
+}
+#[cfg(feature = "async")]
+impl AsyncHttpResponse {
+    pub async fn bytes(mut self) -> Result<Vec<u8>, HttpError> {
+        let mut buf = Vec::new();
+        while let Some(chunk) = self.body.next().await {
+            buf.extend(chunk?);
+        }
+        Ok(buf)
+    }
+
+    pub async fn text(self) -> Result<String, HttpError> {
+        Ok(String::from_utf8(self.bytes().await?)?)
+    }
+}
+
+#[cfg(feature = "async")]
+pub trait AsyncHttpTransport: Send + Sync {
+    fn send(&self, req: HttpRequest) -> BoxFuture<'_, Result<AsyncHttpResponse, HttpError>>;
+}
+
+#[cfg(feature = "async")]
+pub struct AsyncReqwestTransport {
+    client: reqwest::Client,
+}
+#[cfg(feature = "async")]
+impl AsyncReqwestTransport {
+    // The same setup as `ReqwestTransport`, on reqwest's async client
+    pub fn new(conf: &Config) -> Result<Self, Error> {
+        let http = &conf.pull.http;
+        let mut builder = reqwest::Client::builder()
+            .pool_max_idle_per_host(http.pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_secs(http.pool_idle_timeout));
+
+        if let Some(ua) = &http.user_agent {
+            builder = builder.user_agent(ua);
+        }
+        if let Some(proxy) = proxy(conf)? {
+            builder = builder.proxy(proxy);
+        }
+        for cert in ca_certs(conf)? {
+            builder = builder.add_root_certificate(cert);
+        }
+
+        let client = builder.build().map_err(client_error)?;
+        Ok(Self { client })
+    }
+
+    pub fn from_client(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+#[cfg(feature = "async")]
+impl AsyncHttpTransport for AsyncReqwestTransport {
+    fn send(&self, req: HttpRequest) -> BoxFuture<'_, Result<AsyncHttpResponse, HttpError>> {
+        let mut builder = self
+            .client
+            .request(req.method, &req.url)
+            .headers(req.headers);
+        if let Some(b) = req.body {
+            builder = builder.body(b);
+        }
+        if let Some(t) = req.timeout {
+            builder = builder.timeout(t);
+        }
+
+        Box::pin(async move {
+            let resp = builder.send().await?;
+            let status = resp.status();
+            let headers = resp.headers().clone();
+            let body = stream::try_unfold(resp, |mut r| async move {
+                let chunk = r.chunk().await.map_err(HttpError::from)?;
+                Ok(chunk.map(|c| (c.to_vec(), r)))
+            });
+            Ok(AsyncHttpResponse {
+                status,
+                headers,
+                body: body.boxed(),
+            })
+        })
+    }
+}
+
+// The configured proxy, if there is one
+fn proxy(conf: &Config) -> Result<Option<Proxy>, Error> {
+    let p = match &conf.pull.http.proxy {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    // the proxy url can carry a password, so it's never printed
+    Proxy::all(p)
+        .map(Some)
+        .map_err(|_| Error::config("Failed to set up HTTP proxy, the proxy URL is invalid."))
+}
+
+// Extra TLS roots to trust, on top of the system ones
+fn ca_certs(conf: &Config) -> Result<Vec<Certificate>, Error> {
+    conf.pull
+        .http
+        .ca_certs
+        .iter()
+        .map(|path| {
+            fs::read(path)
+                .map_err(|why| why.to_string())
+                .and_then(|f| Certificate::from_pem(&f).map_err(|why| why.to_string()))
+                .map_err(|why| {
+                    Error::config(format!(
+                        "Failed to load CA certificate `{}`, reason: \"{}\".",
+                        path.display(),
+                        why
+                    ))
+                    .with_source(why)
+                })
+        })
+        .collect()
+}
+
+fn client_error(why: reqwest::Error) -> Error {
+    Error::config(format!(
+        "Failed to set up HTTP client, reason: \"{}\".",
+        why
+    ))
+    .with_source(why)
+}
@@ -0,0 +1,451 @@
+// Local archive index, a journal of everything VodBot has archived to disk.
+// Each line of the index file is one JSON record, either inserting or removing
+// an entry. Replaying the journal from the top gives the current state, and
+// `compact` rewrites it down to just the live entries.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::{Config, ConfigDirectories};
+use crate::error::Error;
+use crate::util::{create_dir, get_meta_files};
+use crate::vodbot_api::VodChapter;
+
+#[derive(ValueEnum, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentKind {
+    Vod,
+    Highlight,
+    Premiere,
+    Upload,
+    Clip,
+    Chat,
+}
+impl ContentKind {
+    pub const ALL: [ContentKind; 6] = [
+        ContentKind::Vod,
+        ContentKind::Highlight,
+        ContentKind::Premiere,
+        ContentKind::Upload,
+        ContentKind::Clip,
+        ContentKind::Chat,
+    ];
+
+    pub fn noun(&self) -> &'static str {
+        match self {
+            ContentKind::Vod => "Vod",
+            ContentKind::Highlight => "Highlight",
+            ContentKind::Premiere => "Premiere",
+            ContentKind::Upload => "Upload",
+            ContentKind::Clip => "Clip",
+            ContentKind::Chat => "Chatlog",
+        }
+    }
+
+    pub fn directory<'a>(&self, dirs: &'a ConfigDirectories) -> &'a PathBuf {
+        match self {
+            ContentKind::Vod => &dirs.vods,
+            ContentKind::Highlight => &dirs.highlights,
+            ContentKind::Premiere => &dirs.premieres,
+            ContentKind::Upload => &dirs.uploads,
+            ContentKind::Clip => &dirs.clips,
+            ContentKind::Chat => &dirs.chat,
+        }
+    }
+
+    // Extension of the content file that sits next to each meta file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContentKind::Clip => "mp4",
+            ContentKind::Chat => "chat.json",
+            _ => "mkv",
+        }
+    }
+
+    // Field of the meta file that holds the identifier of the content
+    pub fn id_key(&self) -> &'static str {
+        match self {
+            ContentKind::Clip => "slug",
+            ContentKind::Chat => "videoId",
+            _ => "id",
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        !matches!(self, ContentKind::Chat)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexEntry {
+    pub kind: ContentKind,
+    pub id: String,
+    pub streamer: String,
+    pub path: PathBuf,
+    pub size: usize,
+    pub checksum: String,
+    pub meta: serde_json::Value,
+    // Pruned entries have had their content deleted, but are kept around so
+    // `pull` doesn't download them all over again.
+    #[serde(default)]
+    pub pruned: bool,
+}
+impl IndexEntry {
+    pub fn new(kind: ContentKind, path: PathBuf, meta: serde_json::Value) -> Result<Self, Error> {
+        let (size, checksum) = checksum_file(&path)?;
+        Self::from_parts(kind, path, meta, size, checksum)
+    }
+
+    pub fn new_pruned(
+        kind: ContentKind,
+        path: PathBuf,
+        meta: serde_json::Value,
+    ) -> Result<Self, Error> {
+        let mut e = Self::from_parts(kind, path, meta, 0, "".to_owned())?;
+        e.pruned = true;
+        Ok(e)
+    }
+
+    fn from_parts(
+        kind: ContentKind,
+        path: PathBuf,
+        meta: serde_json::Value,
+        size: usize,
+        checksum: String,
+    ) -> Result<Self, Error> {
+        let id = meta_str(&meta, kind.id_key()).ok_or_else(|| {
+            Error::parse(format!(
+                "Meta for `{}` has no `{}` field.",
+                path.display(),
+                kind.id_key()
+            ))
+        })?;
+        let streamer = meta_str(&meta, "streamerLogin").unwrap_or_default();
+
+        Ok(Self {
+            kind,
+            id,
+            streamer,
+            path,
+            size,
+            checksum,
+            meta,
+            pruned: false,
+        })
+    }
+
+    pub fn meta_str(&self, key: &str) -> String {
+        meta_str(&self.meta, key).unwrap_or_default()
+    }
+
+    pub fn meta_usize(&self, key: &str) -> usize {
+        self.meta.get(key).and_then(|v| v.as_u64()).unwrap_or(0) as usize
+    }
+
+    pub fn meta_path(&self) -> PathBuf {
+        meta_path_for(&self.path, self.kind)
+    }
+
+    // Deserialize the stored meta back into one of the `vodbot_api` structs
+    // Chapters of a vod, clips and vods without any have none
+    pub fn chapters(&self) -> Vec<VodChapter> {
+        self.meta
+            .get("chapters")
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn meta_as<T: for<'de> Deserialize<'de>>(&self) -> Result<T, Error> {
+        serde_json::from_value(self.meta.clone()).map_err(|why| {
+            Error::parse(format!(
+                "Failed to read indexed meta for `{}`, reason: \"{}\".",
+                self.id, why
+            ))
+            .with_source(why)
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum IndexRecord {
+    Insert { entry: IndexEntry },
+    Remove { kind: ContentKind, id: String },
+}
+
+pub struct ArchiveIndex {
+    path: PathBuf,
+    entries: HashMap<(ContentKind, String), IndexEntry>,
+}
+impl ArchiveIndex {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut index = Self {
+            path: path.to_owned(),
+            entries: HashMap::new(),
+        };
+
+        if !path.exists() {
+            log::debug!("no index at {}, starting empty", path.display());
+            return Ok(index);
+        }
+
+        let data = fs::read(path).map_err(|why| {
+            Error::io(format!(
+                "Failed to read index at `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+
+        // A crash in the middle of a write leaves a torn record at the end.
+        // It's cut off here, otherwise the next record appended would be glued
+        // onto it and get skipped along with it.
+        let end = data.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        if end < data.len() {
+            log::warn!("cutting torn record off the end of {}", path.display());
+            fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|f| f.set_len(end as u64))
+                .map_err(|why| {
+                    Error::io(format!(
+                        "Failed to repair index at `{}`, reason: \"{}\".",
+                        path.display(),
+                        why
+                    ))
+                    .with_source(why)
+                })?;
+        }
+
+        for (i, line) in String::from_utf8_lossy(&data[..end]).lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // A bad record only loses itself, everything around it is still good
+            match serde_json::from_str::<IndexRecord>(line) {
+                Ok(r) => index.apply(r),
+                Err(why) => log::warn!(
+                    "skipping bad index record on line {} of {}: {}",
+                    i + 1,
+                    path.display(),
+                    why
+                ),
+            }
+        }
+
+        Ok(index)
+    }
+
+    pub fn load(conf: &Config) -> Result<Self, Error> {
+        Self::open(&conf.directories.index)
+    }
+
+    // Rebuild the index from scratch out of the meta files on disk. Pruned
+    // entries can't be found on disk, so those are carried over as they were.
+    pub fn rebuild(conf: &Config) -> Result<Self, Error> {
+        let mut index = Self {
+            path: conf.directories.index.clone(),
+            entries: Self::load(conf)?
+                .entries
+                .into_iter()
+                .filter(|(_, v)| v.pruned)
+                .collect(),
+        };
+
+        for kind in ContentKind::ALL {
+            for meta_path in get_meta_files(kind.directory(&conf.directories))? {
+                let meta = read_meta(&meta_path)?;
+                let path = content_path_for(&meta_path, kind);
+                let entry = if path.exists() {
+                    IndexEntry::new(kind, path, meta)?
+                } else {
+                    log::debug!("no content next to {}, marking pruned", meta_path.display());
+                    IndexEntry::new_pruned(kind, path, meta)?
+                };
+                log::debug!("indexed {:?} {}", kind, entry.id);
+                index.entries.insert((kind, entry.id.clone()), entry);
+            }
+        }
+
+        index.compact()?;
+        Ok(index)
+    }
+
+    pub fn get(&self, kind: ContentKind, id: &str) -> Option<&IndexEntry> {
+        self.entries
+            .get(&(kind, id.to_owned()))
+            .filter(|f| !f.pruned)
+    }
+
+    // Find a video by id, regardless of what kind it was archived as
+    pub fn find_video(&self, id: &str) -> Option<&IndexEntry> {
+        ContentKind::ALL
+            .iter()
+            .filter(|f| f.is_video())
+            .find_map(|k| self.get(*k, id))
+    }
+
+    // Whether the content was ever archived, even if it has since been pruned
+    pub fn contains(&self, kind: ContentKind, id: &str) -> bool {
+        self.entries.contains_key(&(kind, id.to_owned()))
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &IndexEntry> {
+        self.entries.values().filter(|f| !f.pruned)
+    }
+
+    pub fn entries_of(&self, kind: ContentKind) -> impl Iterator<Item = &IndexEntry> {
+        self.entries().filter(move |f| f.kind == kind)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&mut self, entry: IndexEntry) -> Result<(), Error> {
+        let r = IndexRecord::Insert { entry };
+        self.append(&r)?;
+        self.apply(r);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, kind: ContentKind, id: &str) -> Result<(), Error> {
+        let r = IndexRecord::Remove {
+            kind,
+            id: id.to_owned(),
+        };
+        self.append(&r)?;
+        self.apply(r);
+        Ok(())
+    }
+
+    // Rewrite the journal with only the live entries. We write to a temporary
+    // file first so a crash never leaves a half-written index behind.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        if let Some(p) = self.path.parent() {
+            create_dir(p)?;
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        let mut lines = String::new();
+        for e in self.entries.values() {
+            let r = IndexRecord::Insert { entry: e.clone() };
+            lines += &serde_json::to_string(&r).unwrap();
+            lines += "\n";
+        }
+
+        fs::write(&tmp, lines)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|why| {
+                Error::io(format!(
+                    "Failed to write index at `{}`, reason: \"{}\".",
+                    self.path.display(),
+                    why
+                ))
+                .with_source(why)
+            })
+    }
+
+    fn apply(&mut self, r: IndexRecord) {
+        match r {
+            IndexRecord::Insert { entry } => {
+                self.entries.insert((entry.kind, entry.id.clone()), entry);
+            }
+            IndexRecord::Remove { kind, id } => {
+                self.entries.remove(&(kind, id));
+            }
+        }
+    }
+
+    fn append(&self, r: &IndexRecord) -> Result<(), Error> {
+        if let Some(p) = self.path.parent() {
+            create_dir(p)?;
+        }
+
+        let map_err = |why: std::io::Error| {
+            Error::io(format!(
+                "Failed to write index at `{}`, reason: \"{}\".",
+                self.path.display(),
+                why
+            ))
+            .with_source(why)
+        };
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(map_err)?;
+        let line = serde_json::to_string(r).unwrap() + "\n";
+        file.write_all(line.as_bytes()).map_err(map_err)?;
+        file.sync_data().map_err(map_err)
+    }
+}
+
+pub fn read_meta(path: &Path) -> Result<serde_json::Value, Error> {
+    let file = fs::File::open(path).map_err(|why| {
+        Error::io(format!(
+            "Failed to open meta at `{}`, reason: \"{}\".",
+            path.display(),
+            why
+        ))
+        .with_source(why)
+    })?;
+
+    serde_json::from_reader(file).map_err(|why| {
+        Error::parse(format!(
+            "Failed to parse meta at `{}`, reason: \"{}\".",
+            path.display(),
+            why
+        ))
+        .with_source(why)
+    })
+}
+
+pub fn meta_path_for(content_path: &Path, kind: ContentKind) -> PathBuf {
+    let name = content_path.file_name().unwrap().to_str().unwrap();
+    let stem = name
+        .strip_suffix(&format!(".{}", kind.extension()))
+        .unwrap_or(name);
+    content_path.with_file_name(format!("{}.meta.json", stem))
+}
+
+fn content_path_for(meta_path: &Path, kind: ContentKind) -> PathBuf {
+    let name = meta_path.file_name().unwrap().to_str().unwrap();
+    let stem = name.strip_suffix(".meta.json").unwrap_or(name);
+    meta_path.with_file_name(format!("{}.{}", stem, kind.extension()))
+}
+
+fn meta_str(meta: &serde_json::Value, key: &str) -> Option<String> {
+    meta.get(key).and_then(|v| v.as_str()).map(|v| v.to_owned())
+}
+
+fn checksum_file(path: &Path) -> Result<(usize, String), Error> {
+    let map_err = |why: std::io::Error| {
+        Error::io(format!(
+            "Failed to checksum `{}`, reason: \"{}\".",
+            path.display(),
+            why
+        ))
+        .with_source(why)
+    };
+
+    let mut file = fs::File::open(path).map_err(map_err)?;
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut file, &mut hasher).map_err(map_err)?;
+
+    Ok((size as usize, format!("{:x}", hasher.finalize())))
+}
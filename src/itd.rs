@@ -1,15 +1,19 @@
 // Independent Twitch Downloader, aka the module where it happens
 // a bunch of functions that make it easy to to download videos from Twitch
 
-use std::io::{stdout, Write};
-use std::path::PathBuf;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use m3u8_rs::Playlist;
-use reqwest::blocking::Client;
+use reqwest::header::AUTHORIZATION;
 
 use crate::config::Config;
-use crate::util::{chdir, create_dir, format_size, ExitCode, ExitMsg};
+use crate::error::Error;
+use crate::gql;
+use crate::http::{HttpError, HttpRequest, HttpTransport};
+use crate::progress::{DownloadProgress, ProgressObserver};
+use crate::util::{create_dir, run_ffmpeg};
 use crate::vodbot_api::{Clip, PlaybackAccessToken, Vod};
 
 pub fn download_vod(
@@ -17,125 +21,48 @@ pub fn download_vod(
     vod: Vod,
     token: PlaybackAccessToken,
     output_path: PathBuf,
-    client: &Client,
+    transport: &Arc<dyn HttpTransport>,
     noun: String,
-) -> Result<Vod, ExitMsg> {
-    print!("\r{} `{}` ...", noun, vod.id);
-    stdout().flush().unwrap();
+    progress: &dyn ProgressObserver,
+) -> Result<Vod, Error> {
+    progress.started(&noun, &vod.id);
+    let start_time = Instant::now();
 
     // get m3u8 quality playlist, first uri is the source quality
-    let mut uri = get_playlist_source_uri(&vod, token, client)?;
+    let resp = transport
+        .send(playlist_request(conf, &vod, token)?)
+        .map_err(|why| network_error("get M3U8 playlist from Twitch", why))?;
+    let bytes = resp
+        .bytes()
+        .map_err(|why| network_error("read M3U8 playlist from Twitch", why))?;
+    let uri = source_uri(&bytes)?;
 
     // then we use that uri to grab the video segment playlist, also m3u8
-    let resp = client.get(&uri).send().map_err(|why| {
-        ExitMsg::new(
-            ExitCode::PullCannotGetSourcePlaylist,
-            format!("Failed to get source M3U8 playlist, reason: \"{}\".", why,),
-        )
-    })?;
-    let bytes = resp.bytes().map_err(|why| {
-        ExitMsg::new(
-            ExitCode::PullCannotReadSourcePlaylist,
-            format!("Failed to read source M3U8 playlist, reason: \"{}\".", why,),
-        )
-    })?;
-    let playlist = m3u8_rs::parse_playlist(&bytes.clone())
-        .map_err(|why| {
-            ExitMsg::new(
-                ExitCode::PullCannotParseSourcePlaylist,
-                format!("Failed to parse source M3U8 playlist, reason: \"{}\".", why,),
-            )
-        })?
-        .1;
-
-    let p = match playlist {
-        Playlist::MediaPlaylist(p) => Ok(p),
-        _ => Err(ExitMsg::new(
-            ExitCode::PullCannotUseSourcePlaylist,
-            format!("Failed to use source M3U8 playlist."),
-        )),
-    }?;
+    let resp = transport
+        .send(HttpRequest::get(&uri))
+        .map_err(|why| network_error("get source M3U8 playlist", why))?;
+    let bytes = resp
+        .bytes()
+        .map_err(|why| network_error("read source M3U8 playlist", why))?;
 
     // then we determine what paths each segment should have
-    let temp_dir = &conf.directories.temp.clone().join(vod.id.clone());
-    create_dir(temp_dir)?;
-
-    let split_idx = uri.clone().rfind("/").unwrap() + 1;
-    uri.split_off(split_idx).truncate(split_idx);
-
-    let playlist_path = temp_dir.clone().join("playlist.m3u8");
-    let segment_uri_paths: Vec<_> = p
-        .segments
-        .iter()
-        .map(|f| (temp_dir.clone().join(f.uri.clone()), uri.clone() + &f.uri))
-        .collect();
+    let segments = Segments::new(conf, &vod, &uri, &bytes)?;
+    create_dir(&segments.temp_dir)?;
+    std::fs::write(&segments.playlist_path, &segments.playlist).map_err(playlist_error)?;
 
     // then we start the workers on downloading each segment
-    std::fs::write(&playlist_path, &bytes).map_err(|why| {
-        ExitMsg::new(
-            ExitCode::PullCannotWriteSourcePlaylist,
-            format!(
-                "Failed to use write M3U8 playlist to disk, reason \"{}\".",
-                why
-            ),
-        )
-    })?;
-    workers_download(conf, &vod, segment_uri_paths, client, noun)?;
+    let size = workers_download(conf, &vod, segments.paths.clone(), transport, progress)?;
 
     // once the download is done, we spawn an ffmpeg process to stitch it all together
-    let currdir = std::env::current_dir().unwrap(); // TODO: this is dangerous, we should fix this.
-    chdir(temp_dir)?;
-    let loglevel = format!("{:?}", conf.export.ffmpeg_loglevel).to_lowercase();
-    let status = std::process::Command::new("ffmpeg")
-        .args([
-            "-i",
-            playlist_path.to_str().unwrap(),
-            "-max_interleave_delta",
-            "0",
-            "-c",
-            "copy",
-            output_path.to_str().unwrap(),
-            "-y",
-            "-stats",
-            "-loglevel",
-            &loglevel,
-        ])
-        .status()
-        .map_err(|why| {
-            ExitMsg::new(
-                ExitCode::CannotStartFfmpeg,
-                format!("Failed to start FFMPEG, reason \"{}\".", why),
-            )
-        })?;
-    chdir(&currdir)?;
     // TODO: sometimes segments are called corrupt by ffmpeg
     // most of the time theyre useable, depending on the version of ffmpeg
     // the streams seem otherwise fine, but maybe we should figure out whats going wrong?
-
-    // check that ffmpeg returned as expected, raise error if necessary
-    let status = status.code();
-    if let Some(s) = status {
-        if s != 0 {
-            return Err(ExitMsg::new(
-                ExitCode::FfmpegReturnedError,
-                format!("FFMPEG returned a non-zero status, `{}`.", s),
-            ));
-        }
-    } else if let None = status {
-        return Err(ExitMsg::new(
-            ExitCode::FfmpegInterrupted,
-            format!("FFMPEG was interrupted, no other error."),
-        ));
-    }
+    run_ffmpeg(conf, &stitch_args(&segments.playlist_path, &output_path))?;
 
     // clear out the temp folder, and we're done here!
-    std::fs::remove_dir_all(temp_dir).map_err(|why| {
-        ExitMsg::new(
-            ExitCode::PullCannotCleanUpAfterDownload,
-            format!("Failed to clean up after Vod download, reason \"{}\".", why),
-        )
-    })?;
+    std::fs::remove_dir_all(&segments.temp_dir).map_err(cleanup_error)?;
 
+    progress.finished(&vod.id, size, start_time.elapsed().as_secs_f32());
     Ok(vod)
 }
 
@@ -144,46 +71,91 @@ pub fn download_clip(
     clip: Clip,
     token: PlaybackAccessToken,
     output_path: PathBuf,
-    client: &Client,
-    _noun: String,
-) -> Result<Clip, ExitMsg> {
-    print!("\rClip `{}` ...", clip.slug);
-    stdout().flush().unwrap();
-
-    // get the cdn url
-    let url = reqwest::Url::parse_with_params(
-        &clip.source_url,
-        &[("token", token.value), ("sig", token.signature)],
-    )
-    .unwrap();
+    transport: &Arc<dyn HttpTransport>,
+    noun: String,
+    progress: &dyn ProgressObserver,
+) -> Result<Clip, Error> {
+    progress.started(&noun, &clip.slug);
 
-    // download using this url
-    let start_time = std::time::Instant::now();
+    // download using the cdn url
+    let start_time = Instant::now();
     let size = download_file(
-        url.to_string(),
+        clip_url(&clip, token),
         output_path,
         conf.pull.connection_timeout,
-        client,
+        transport,
     )?;
-
-    // print stats
-    println!(
-        "\rClip `{}` -- {: >8} in {:.1} seconds",
-        clip.slug,
-        format_size(size, 1, true),
-        start_time.elapsed().as_secs_f32()
-    );
+    let d32 = start_time.elapsed().as_secs_f32();
+    progress.progress(&clip_progress(&clip, size, d32));
+    progress.finished(&clip.slug, size, d32);
 
     Ok(clip)
 }
 
-fn get_playlist_source_uri(
+// Where a vod's segments go while downloading, and the playlist ffmpeg stitches
+// them together from
+pub(crate) struct Segments {
+    pub(crate) temp_dir: PathBuf,
+    pub(crate) playlist_path: PathBuf,
+    pub(crate) playlist: Vec<u8>,
+    // the path each segment is saved to, and the url it comes from
+    pub(crate) paths: Vec<(PathBuf, String)>,
+}
+impl Segments {
+    pub(crate) fn new(conf: &Config, vod: &Vod, uri: &str, bytes: &[u8]) -> Result<Self, Error> {
+        let mut p = match m3u8_rs::parse_playlist_res(bytes) {
+            Ok(Playlist::MediaPlaylist(p)) => p,
+            Ok(_) => return Err(Error::parse("Failed to use source M3U8 playlist.")),
+            Err(why) => {
+                return Err(Error::parse(format!(
+                    "Failed to parse source M3U8 playlist, reason: \"{}\".",
+                    why,
+                )))
+            }
+        };
+
+        // ffmpeg is handed absolute paths, so it finds the segments no matter
+        // where it's run from
+        let temp_dir = std::path::absolute(conf.directories.temp.join(&vod.id)).map_err(|why| {
+            Error::io(format!(
+                "Failed to find temp directory for Vod download, reason \"{}\".",
+                why
+            ))
+            .with_source(why)
+        })?;
+        let base = &uri[..uri.rfind('/').map_or(0, |i| i + 1)];
+
+        let mut paths = Vec::new();
+        for s in &mut p.segments {
+            let path = temp_dir.join(&s.uri);
+            paths.push((path.clone(), format!("{}{}", base, s.uri)));
+            s.uri = path.to_str().unwrap().to_owned();
+        }
+
+        let mut playlist = Vec::new();
+        p.write_to(&mut playlist).map_err(playlist_error)?;
+
+        Ok(Self {
+            playlist_path: temp_dir.join("playlist.m3u8"),
+            temp_dir,
+            playlist,
+            paths,
+        })
+    }
+}
+
+pub(crate) fn playlist_request(
+    conf: &Config,
     vod: &Vod,
     token: PlaybackAccessToken,
-    client: &Client,
-) -> Result<String, ExitMsg> {
+) -> Result<HttpRequest, Error> {
     let url = reqwest::Url::parse_with_params(
-        format!("http://usher.ttvnw.net/vod/{}", vod.id).as_str(),
+        format!(
+            "{}/vod/{}",
+            conf.pull.usher_url.trim_end_matches('/'),
+            vod.id
+        )
+        .as_str(),
         &[
             ("nauth", token.value),
             ("nauthsig", token.signature),
@@ -192,163 +164,191 @@ fn get_playlist_source_uri(
         ],
     )
     .unwrap();
-    let resp = client
-        .get(url)
-        // TODO: Change this duration?
-        .timeout(Duration::from_secs(5))
-        .send()
-        .map_err(|why| {
-            ExitMsg::new(
-                ExitCode::PullCannotGetPlaylistURI,
-                format!(
-                    "Failed to get M3U8 playlist from Twitch, reason: \"{}\".",
-                    why,
-                ),
-            )
-        })?;
+    // TODO: Change this duration?
+    let mut req = HttpRequest::get(url.as_str()).timeout(Duration::from_secs(5));
+    if let Some(o) = gql::oauth_header(conf)? {
+        // never send the login over plain http
+        if url.scheme() != "https" {
+            return Err(Error::config(
+                "Twitch OAuth token is set, but the usher URL isn't https.",
+            ));
+        }
+        req = req.header(AUTHORIZATION, o);
+    }
+    Ok(req)
+}
 
-    let bytes = resp.bytes().map_err(|why| {
-        ExitMsg::new(
-            ExitCode::PullCannotReadPlaylistURI,
-            format!(
-                "Failed to read M3U8 playlist from Twitch, reason: \"{}\".",
-                why,
-            ),
-        )
+// The source quality's playlist out of Twitch's master playlist
+pub(crate) fn source_uri(bytes: &[u8]) -> Result<String, Error> {
+    let playlist = m3u8_rs::parse_playlist_res(bytes).map_err(|why| {
+        Error::parse(format!(
+            "Failed to parse M3U8 playlist from Twitch, reason: \"{}\".",
+            why,
+        ))
     })?;
 
-    let playlist = m3u8_rs::parse_playlist(&bytes)
-        .map_err(|why| {
-            ExitMsg::new(
-                ExitCode::PullCannotParsePlaylistURI,
-                format!(
-                    "Failed to parse M3U8 playlist from Twitch, reason: \"{}\".",
-                    why,
-                ),
-            )
-        })?
-        .1;
-
-    if let Playlist::MasterPlaylist(p) = playlist {
-        Ok(p.variants.first().unwrap().uri.to_owned())
-    } else {
-        Err(ExitMsg::new(
-            ExitCode::PullCannotFindPlaylistURI,
-            format!("Failed to find source M3U8 playlist URI from Twitch."),
-        ))
+    match playlist {
+        Playlist::MasterPlaylist(p) if !p.variants.is_empty() => Ok(p.variants[0].uri.to_owned()),
+        _ => Err(Error::not_found(
+            "Failed to find source M3U8 playlist URI from Twitch.",
+        )),
+    }
+}
+
+pub(crate) fn stitch_args<'a>(playlist_path: &'a Path, output_path: &'a Path) -> [&'a str; 7] {
+    [
+        "-i",
+        playlist_path.to_str().unwrap(),
+        "-max_interleave_delta",
+        "0",
+        "-c",
+        "copy",
+        output_path.to_str().unwrap(),
+    ]
+}
+
+pub(crate) fn clip_url(clip: &Clip, token: PlaybackAccessToken) -> String {
+    reqwest::Url::parse_with_params(
+        &clip.source_url,
+        &[("token", token.value), ("sig", token.signature)],
+    )
+    .unwrap()
+    .to_string()
+}
+
+pub(crate) fn clip_progress(clip: &Clip, size: usize, seconds: f32) -> DownloadProgress {
+    DownloadProgress {
+        id: clip.slug.clone(),
+        parts_done: 1,
+        parts_total: 1,
+        bytes: size,
+        estimated_bytes: size,
+        bytes_per_second: ((size as f32) / seconds) as usize,
+        seconds_left: 0.0,
     }
 }
 
+// How far along a vod is after `done` of its `total` segments came in
+pub(crate) fn segment_progress(
+    vod: &Vod,
+    done: usize,
+    total: usize,
+    bytes: usize,
+    start_time: Instant,
+) -> DownloadProgress {
+    let perc = (done as f32) / (total as f32);
+    let d32 = start_time.elapsed().as_secs_f32();
+    DownloadProgress {
+        id: vod.id.clone(),
+        parts_done: done,
+        parts_total: total,
+        bytes,
+        estimated_bytes: ((bytes as f32) / perc) as usize,
+        bytes_per_second: ((bytes as f32) / d32) as usize,
+        seconds_left: ((total - done) as f32) * d32 / (done as f32),
+    }
+}
+
+pub(crate) fn network_error(what: &str, why: HttpError) -> Error {
+    Error::network(format!("Failed to {}, reason: \"{}\".", what, why)).with_source(why)
+}
+
+pub(crate) fn write_error(why: std::io::Error) -> Error {
+    Error::io(format!("Failed to write file, reason \"{}\".", why)).with_source(why)
+}
+
+pub(crate) fn playlist_error(why: std::io::Error) -> Error {
+    Error::io(format!(
+        "Failed to use write M3U8 playlist to disk, reason \"{}\".",
+        why
+    ))
+    .with_source(why)
+}
+
+pub(crate) fn cleanup_error(why: std::io::Error) -> Error {
+    Error::io(format!(
+        "Failed to clean up after Vod download, reason \"{}\".",
+        why
+    ))
+    .with_source(why)
+}
+
 fn workers_download(
     conf: &Config,
     vod: &Vod,
     paths: Vec<(PathBuf, String)>,
-    client: &Client,
-    noun: String,
-) -> Result<(), ExitMsg> {
+    transport: &Arc<dyn HttpTransport>,
+    progress: &dyn ProgressObserver,
+) -> Result<usize, Error> {
     let executor = threadpool::ThreadPool::new(conf.pull.download_workers);
 
     let timeout = conf.pull.connection_timeout;
 
     let (tx, rx) = std::sync::mpsc::channel();
 
-    let c = std::sync::Arc::new(client.to_owned());
+    let c = transport.clone();
 
     let total_count = paths.len();
 
-    let start_time = std::time::Instant::now();
+    let start_time = Instant::now();
 
     for (p, u) in paths {
         let tx = tx.clone();
         let c = c.clone();
         executor.execute(move || {
-            let _ = tx.send(download_file(u, p, timeout.clone(), &c))
-                .map_err(|f| ExitMsg::new(
-                    ExitCode::PullFailedToSendOnChannel,
-                    format!("Failed to send on chunk channel, reason: \"{}\".", f.to_string())
-                ));
+            let _ = tx.send(download_file(u, p, timeout, &c)).map_err(|f| {
+                Error::internal(format!(
+                    "Failed to send on chunk channel, reason: \"{}\".",
+                    f
+                ))
+                .with_source(f)
+            });
         });
     }
 
     let mut done_count: usize = 0;
     let mut dl_size: usize = 0;
-    loop {
+    while done_count < total_count {
         done_count += 1;
 
-        dl_size += rx.recv().map_err(|f| ExitMsg::new(
-            ExitCode::PullFailedToRecieveOnChannel,
-            format!("Failed to recieve bytes from job pool. Reason: \"{}\"", f)
-        ))??;
-
-        let perc = (done_count as f32) / (total_count as f32);
-        let est_size = ((dl_size as f32) / perc) as usize;
-        let duration = start_time.elapsed();
-        let d32 = duration.as_secs_f32();
-        let dl_speed = ((dl_size as f32) / d32) as usize;
-        let time_left = ((total_count - done_count) as f32) * d32 / (done_count as f32);
-
-        if done_count >= total_count {
-            print!("\r{}", " ".to_owned().repeat(80));
-            println!(
-                "\r{} `{}` -- {: >8} in {:.1} seconds",
-                noun,
-                vod.id,
-                format_size(dl_size, 1, true),
-                d32
-            );
-            break;
-        } else {
-            print!("\r{}", " ".to_owned().repeat(80));
-            print!(
-                "\r{} `{}` -- {: >3.0}% -- {: >8} of {: >8} (@ {: >8}/s) -- ({: >4.0}s left)",
-                noun,
-                vod.id,
-                perc * 100f32,
-                format_size(dl_size, 1, true),
-                format_size(est_size, 1, true),
-                format_size(dl_speed, 1, true),
-                time_left
-            );
-            stdout().flush().unwrap();
-        }
+        dl_size += rx.recv().map_err(|f| {
+            Error::internal(format!(
+                "Failed to recieve bytes from job pool. Reason: \"{}\"",
+                f
+            ))
+            .with_source(f)
+        })??;
+
+        progress.progress(&segment_progress(
+            vod,
+            done_count,
+            total_count,
+            dl_size,
+            start_time,
+        ));
     }
 
     log::trace!("Rejoining thread pool.");
     executor.join();
 
-    Ok(())
+    Ok(dl_size)
 }
 
 fn download_file(
     url: String,
     path: PathBuf,
     timeout: usize,
-    client: &Client,
-) -> Result<usize, ExitMsg> {
-    let resp = client
-        .get(url)
-        .timeout(Duration::from_secs(timeout as u64))
-        .send()
-        .map_err(|why| {
-            ExitMsg::new(
-                ExitCode::PullCannotGetChunk,
-                format!("Failed to get file, reason \"{}\".", why),
-            )
-        })?;
+    transport: &Arc<dyn HttpTransport>,
+) -> Result<usize, Error> {
+    let resp = transport
+        .send(HttpRequest::get(&url).timeout(Duration::from_secs(timeout as u64)))
+        .map_err(|why| network_error("get file", why))?;
 
-    let bytes = resp.bytes().map_err(|why| {
-        ExitMsg::new(
-            ExitCode::PullCannotParseChunk,
-            format!("Failed to parse file, reason \"{}\".", why),
-        )
-    })?;
+    let bytes = resp
+        .bytes()
+        .map_err(|why| network_error("read file", why))?;
 
-    std::fs::write(path, &bytes).map_err(|why| {
-        ExitMsg::new(
-            ExitCode::PullCannotWriteChunk,
-            format!("Failed to write file, reason \"{}\".", why),
-        )
-    })?;
+    std::fs::write(path, &bytes).map_err(write_error)?;
 
     Ok(bytes.len())
 }
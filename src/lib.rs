@@ -21,16 +21,39 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR
 // OTHER DEALINGS IN THE SOFTWARE.
 
+pub mod chat;
 pub mod cli;
 pub mod config;
+pub mod description;
+pub mod error;
 pub mod gql;
+pub mod gql_query;
+pub mod http;
+pub mod index;
 pub mod itd;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+pub mod oauth;
+pub mod progress;
+pub mod template;
+pub mod thumbnail;
+pub mod timestamp;
 pub mod twitch;
 pub mod twitch_api;
 pub mod util;
 pub mod vodbot_api;
+pub mod youtube;
 pub mod commands {
+    pub mod export;
     pub mod info;
     pub mod init;
+    pub mod login;
+    pub mod logout;
+    pub mod prune;
     pub mod pull;
+    pub mod reindex;
+    pub mod search;
+    pub mod stage;
+    pub mod stats;
+    pub mod upload;
 }
@@ -2,14 +2,85 @@
 
 use vodbot::cli::{Cli, Commands};
 use vodbot::commands;
+use vodbot::commands::search::SearchFilter;
+use vodbot::error::Error;
 use vodbot::util;
 
 use clap::Parser;
 
-fn deffered_main() -> Result<(), util::ExitMsg> {
+// Self-describing exit codes.
+// Each exit point of the program should be using a very clear exit code, along
+// with a message sent to stderr for more details. Certain codes may be reserved
+// or not used, as indicated by the leading underscore in its name.
+#[derive(Debug, Clone)]
+enum ExitCode {
+    // Special codes
+    _CleanExit,
+    Interrupted,
+    _ReservedByClap,
+    StderrLoggerError,
+    CannotRegisterSignalHandler,
+
+    // One for each kind of library error
+    BadConfig,
+    IOError,
+    NetworkError,
+    GQLErrorFromTwitch,
+    NotFound,
+    CannotParse,
+    FfmpegError,
+    BadInput,
+    NotAuthorized,
+    InternalError,
+}
+impl From<&Error> for ExitCode {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::Config { .. } => ExitCode::BadConfig,
+            Error::Io { .. } => ExitCode::IOError,
+            Error::Network { .. } => ExitCode::NetworkError,
+            Error::Gql { .. } => ExitCode::GQLErrorFromTwitch,
+            Error::NotFound { .. } => ExitCode::NotFound,
+            Error::Parse { .. } => ExitCode::CannotParse,
+            Error::Ffmpeg { .. } => ExitCode::FfmpegError,
+            Error::Input { .. } => ExitCode::BadInput,
+            Error::Auth { .. } => ExitCode::NotAuthorized,
+            Error::Internal { .. } => ExitCode::InternalError,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ExitMsg {
+    code: ExitCode,
+    msg: String,
+}
+impl ExitMsg {
+    fn new(code: ExitCode, msg: String) -> Self {
+        ExitMsg { code, msg }
+    }
+}
+impl From<Error> for ExitMsg {
+    fn from(e: Error) -> Self {
+        ExitMsg::new(ExitCode::from(&e), e.to_string())
+    }
+}
+impl std::fmt::Display for ExitMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} Exit code: {:?} ({})",
+            self.msg.as_str(),
+            self.code,
+            self.code.clone() as i32
+        )
+    }
+}
+
+fn deffered_main() -> Result<(), ExitMsg> {
     // Setup the SIGINT handler
     ctrlc::set_handler(move || {
-        let e = util::ExitMsg::new(util::ExitCode::Interrupted, "Interrupted!".to_owned());
+        let e = ExitMsg::new(ExitCode::Interrupted, "Interrupted!".to_owned());
         println!(
             " Interrupted!\nExit code: {:?} ({})",
             e.code.clone(),
@@ -18,8 +89,8 @@ fn deffered_main() -> Result<(), util::ExitMsg> {
         std::process::exit(e.code as i32);
     })
     .map_err(|why| {
-        util::ExitMsg::new(
-            util::ExitCode::CannotRegisterSignalHandler,
+        ExitMsg::new(
+            ExitCode::CannotRegisterSignalHandler,
             format!(
                 "Cannot register signal interrupt handler, reason: \"{}\".",
                 why
@@ -36,8 +107,8 @@ fn deffered_main() -> Result<(), util::ExitMsg> {
         .verbosity(args.verbose as usize)
         .init()
         .map_err(|e| {
-            util::ExitMsg::new(
-                util::ExitCode::StderrLoggerError,
+            ExitMsg::new(
+                ExitCode::StderrLoggerError,
                 format!(
                     "Failed to initialize stderr logger, reason: \"{}\".",
                     e.to_string()
@@ -54,15 +125,59 @@ fn deffered_main() -> Result<(), util::ExitMsg> {
         Commands::Init { overwrite_confirm } => commands::init::run(overwrite_confirm)?,
         Commands::Info { json, strings } => commands::info::run(config_path, json, strings)?,
         Commands::Pull { mode } => commands::pull::run(config_path, mode)?,
-        Commands::Stage { command } => {
-            println!("stage! {:?}", command);
-        }
-        Commands::Export { stage_id, path } => {
-            println!("export! {} {}", stage_id, path.to_str().unwrap());
+        Commands::Stage { command } => commands::stage::run(config_path, command)?,
+        Commands::Search {
+            kind,
+            streamer,
+            game,
+            title,
+            after,
+            before,
+            min_duration,
+            max_duration,
+            chat,
+            json,
+            ids_only,
+        } => {
+            let filter = SearchFilter {
+                kinds: kind,
+                streamers: streamer,
+                game,
+                title,
+                after,
+                before,
+                min_duration,
+                max_duration,
+                chat,
+            };
+            commands::search::run(config_path, filter, json, ids_only)?
         }
-        Commands::Upload { stage_id } => {
-            println!("upload! {}", stage_id);
+        Commands::Prune {
+            channels,
+            dry_run,
+            confirm,
+            keep_chat,
+            keep_meta,
+        } => commands::prune::run(
+            config_path,
+            channels,
+            dry_run,
+            confirm,
+            keep_chat,
+            keep_meta,
+        )?,
+        Commands::Stats { json, top } => commands::stats::run(config_path, json, top)?,
+        Commands::Reindex => commands::reindex::run(config_path)?,
+        Commands::Export {
+            stage_id,
+            path,
+            preset,
+        } => commands::export::run(config_path, stage_id, path, preset)?,
+        Commands::Upload { stage_id, preset } => {
+            commands::upload::run(config_path, stage_id, preset)?
         }
+        Commands::Login { service } => commands::login::run(config_path, service)?,
+        Commands::Logout { service } => commands::logout::run(config_path, service)?,
     }
 
     // We're done here!
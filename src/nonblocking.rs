@@ -0,0 +1,413 @@
+// Async versions of the GQL client, the Twitch queries and downloads, for
+// embedding VodBot in tokio apps. Everything goes through reqwest's async
+// client, and shares its queries, rate limiting and retries with the blocking
+// versions, so the two always ask Twitch the same things.
+//
+// Progress on downloads is reported to a `ProgressObserver` as it happens,
+// closures work too.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::stream::{self, StreamExt};
+use tokio::io::AsyncWriteExt;
+
+use crate::config::{Config, GQLMode};
+use crate::error::Error;
+use crate::gql::{self, GQLState, Reply};
+use crate::http::{AsyncHttpTransport, AsyncReqwestTransport, HttpRequest};
+use crate::itd::{self, Segments};
+use crate::progress::ProgressObserver;
+use crate::twitch::{self, Batch, BatchResult, Chunk};
+use crate::twitch_api::{TwitchClip, TwitchData, TwitchResponse, TwitchUser, TwitchVideo};
+use crate::util;
+use crate::vodbot_api::{ChatMessage, Clip, PlaybackAccessToken, Vod, VodChapter};
+
+pub struct GQLClient {
+    state: GQLState,
+    transport: Arc<dyn AsyncHttpTransport>,
+}
+impl GQLClient {
+    pub fn new(conf: &Config) -> Result<Self, Error> {
+        Self::with_transport(conf, Arc::new(AsyncReqwestTransport::new(conf)?))
+    }
+
+    pub fn with_transport(
+        conf: &Config,
+        transport: Arc<dyn AsyncHttpTransport>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            state: GQLState::new(conf)?,
+            transport,
+        })
+    }
+
+    // The transport queries go through, for sharing with downloads
+    pub fn transport(&self) -> Arc<dyn AsyncHttpTransport> {
+        self.transport.clone()
+    }
+
+    pub async fn raw_query(&self, query: String) -> Result<String, Error> {
+        if self.state.mode == GQLMode::Replay {
+            let path = self.state.fixture_path(&query);
+            let data = tokio::fs::read_to_string(&path).await;
+            return self.state.replay(&query, &path, data);
+        }
+
+        let text = self.send(&query).await?;
+
+        if self.state.mode == GQLMode::Record {
+            tokio::fs::create_dir_all(&self.state.fixtures)
+                .await
+                .map_err(|why| gql::fixture_error(&self.state.fixtures, why))?;
+            let (path, fixture) = self.state.record(&query, &text);
+            tokio::fs::write(&path, fixture)
+                .await
+                .map_err(|why| gql::fixture_error(&path, why))?;
+        }
+
+        Ok(text)
+    }
+
+    pub async fn query<T>(&self, query: String) -> Result<TwitchResponse<T>, Error>
+    where
+        T: TwitchData + for<'de> serde::Deserialize<'de>,
+    {
+        let mut tries = 0;
+        loop {
+            let s = self.raw_query(query.clone()).await?;
+            if let Some(j) = self.state.answer(&query, &s, &mut tries)? {
+                return Ok(j);
+            }
+        }
+    }
+
+    // Post a query, waiting for our turn under the rate limit and retrying
+    // when Twitch throttles us or has trouble of its own
+    async fn send(&self, query: &str) -> Result<String, Error> {
+        let mut tries = 0;
+        loop {
+            tokio::time::sleep_until(self.state.next_turn().into()).await;
+
+            let (err, wait) = match self.transport.send(self.state.request(query)).await {
+                Ok(r) => match self.state.reply(r.status, &r.headers, tries) {
+                    Reply::Success => return r.text().await.map_err(gql::read_error),
+                    Reply::Retry(wait) => (gql::status_error(r.status), wait),
+                    Reply::Fail => {
+                        return Err(gql::body_error(&r.text().await.unwrap_or_default()))
+                    }
+                },
+                Err(why) => (gql::connect_error(why), gql::backoff(tries + 1)),
+            };
+
+            self.state.retry(&mut tries, err, wait)?;
+        }
+    }
+}
+
+// The async `batched_query`, chunks in flight at once share the one task
+async fn batched_query<T: TwitchData + for<'de> serde::Deserialize<'de>, R>(
+    batch: Batch<T, R>,
+    client: &GQLClient,
+    var: &[String],
+) -> Result<BatchResult<R>, Error> {
+    let chunks: Vec<_> = var.chunks(client.state.batch_size).collect();
+    log::debug!(
+        "querying {} ids in {} chunks, {} at a time",
+        var.len(),
+        chunks.len(),
+        client.state.parallel_batches
+    );
+
+    // the futures are made up front, so the stream holds no closure and the
+    // whole thing stays `Send` for spawning
+    let queries: Vec<_> = chunks
+        .into_iter()
+        .map(|c| query_chunk(&batch, client, c))
+        .collect();
+    let results = stream::iter(queries)
+        .buffer_unordered(client.state.parallel_batches)
+        .collect()
+        .await;
+
+    twitch::merge(results)
+}
+
+async fn query_chunk<T: TwitchData + for<'de> serde::Deserialize<'de>, R>(
+    batch: &Batch<T, R>,
+    client: &GQLClient,
+    var: &[String],
+) -> Result<BatchResult<R>, Error> {
+    let mut chunk = Chunk::new(batch, var);
+    while let Some(q) = chunk.next_query() {
+        chunk.answer(client.query(q).await?);
+    }
+    Ok(chunk.finish())
+}
+
+pub async fn get_channels_videos(
+    client: &GQLClient,
+    user_logins: &[String],
+    video_type: String,
+) -> Result<BatchResult<Vod>, Error> {
+    log::debug!("getting video(s) of type {}", video_type);
+
+    let mut vods = batched_query(twitch::channels_videos(&video_type), client, user_logins).await?;
+
+    let chapters = get_videos_chapters(client, &twitch::vod_ids(&vods)).await?;
+    twitch::add_chapters(&mut vods, chapters);
+
+    Ok(vods)
+}
+
+pub async fn get_channel_videos(
+    client: &GQLClient,
+    user_login: String,
+    video_type: String,
+) -> Result<Vec<Vod>, Error> {
+    twitch::single(
+        get_channels_videos(client, std::slice::from_ref(&user_login), video_type)
+            .await?
+            .found,
+        &user_login,
+    )
+}
+
+// Getters for each type of video, for a list of channels and for just the one
+macro_rules! videos_getters {
+    ($($many:ident, $one:ident => $video_type:expr,)*) => {
+        $(
+            pub async fn $many(
+                client: &GQLClient,
+                user_logins: &[String],
+            ) -> Result<BatchResult<Vod>, Error> {
+                get_channels_videos(client, user_logins, $video_type.to_owned()).await
+            }
+
+            pub async fn $one(client: &GQLClient, user_login: String) -> Result<Vec<Vod>, Error> {
+                get_channel_videos(client, user_login, $video_type.to_owned()).await
+            }
+        )*
+    };
+}
+
+videos_getters! {
+    get_channels_videos_archive, get_channel_videos_archive => twitch::ARCHIVE,
+    get_channels_videos_highlight, get_channel_videos_highlight => twitch::HIGHLIGHT,
+    get_channels_videos_upload, get_channel_videos_upload => twitch::UPLOAD,
+    get_channels_videos_premiere, get_channel_videos_premiere => twitch::PREMIERE,
+}
+
+// Getters that take a list of ids, and the same for just the one id
+macro_rules! batch_getters {
+    ($($many:ident, $one:ident => $batch:expr, $ret:ty,)*) => {
+        $(
+            pub async fn $many(
+                client: &GQLClient,
+                ids: &[String],
+            ) -> Result<BatchResult<$ret>, Error> {
+                batched_query($batch, client, ids).await
+            }
+
+            pub async fn $one(client: &GQLClient, id: String) -> Result<Vec<$ret>, Error> {
+                twitch::single($many(client, std::slice::from_ref(&id)).await?.found, &id)
+            }
+        )*
+    };
+}
+
+batch_getters! {
+    get_channels_clips, get_channel_clips => twitch::channels_clips(), Clip,
+    get_videos_comments, get_video_comments => twitch::videos_comments(), ChatMessage,
+    get_videos_chapters, get_video_chapters => twitch::videos_chapters(), VodChapter,
+}
+
+// Same again for tokens, one per id
+macro_rules! token_getters {
+    ($($many:ident, $one:ident => $batch:expr,)*) => {
+        $(
+            pub async fn $many(
+                client: &GQLClient,
+                ids: &[String],
+            ) -> Result<HashMap<String, PlaybackAccessToken>, Error> {
+                Ok(twitch::tokens(batched_query($batch, client, ids).await?))
+            }
+
+            pub async fn $one(client: &GQLClient, id: String) -> Result<PlaybackAccessToken, Error> {
+                twitch::single($many(client, std::slice::from_ref(&id)).await?, &id)
+            }
+        )*
+    };
+}
+
+token_getters! {
+    get_videos_playback_access_tokens, get_video_playback_access_token =>
+        twitch::videos_playback_access_tokens(),
+    get_clips_playback_access_tokens, get_clip_playback_access_token =>
+        twitch::clips_playback_access_tokens(),
+}
+
+pub async fn get_channel(
+    client: &GQLClient,
+    user_login: String,
+) -> Result<Option<TwitchUser>, Error> {
+    log::trace!("getting channel info for {}", user_login);
+
+    Ok(twitch::only(
+        client.query(twitch::channel_query(user_login)).await?,
+    ))
+}
+
+pub async fn get_video(client: &GQLClient, video_id: String) -> Result<Option<TwitchVideo>, Error> {
+    log::trace!("getting video info for {}", video_id);
+
+    Ok(twitch::only(
+        client.query(twitch::video_query(video_id)).await?,
+    ))
+}
+
+pub async fn get_clip(client: &GQLClient, clip_slug: String) -> Result<Option<TwitchClip>, Error> {
+    log::trace!("getting clip info for {}", clip_slug);
+
+    Ok(twitch::only(
+        client.query(twitch::clip_query(clip_slug)).await?,
+    ))
+}
+
+pub async fn download_vod(
+    conf: &Config,
+    vod: Vod,
+    token: PlaybackAccessToken,
+    output_path: PathBuf,
+    transport: &Arc<dyn AsyncHttpTransport>,
+    noun: String,
+    progress: &dyn ProgressObserver,
+) -> Result<Vod, Error> {
+    progress.started(&noun, &vod.id);
+    let start_time = Instant::now();
+
+    // get m3u8 quality playlist, first uri is the source quality
+    let resp = transport
+        .send(itd::playlist_request(conf, &vod, token)?)
+        .await
+        .map_err(|why| itd::network_error("get M3U8 playlist from Twitch", why))?;
+    let bytes = resp
+        .bytes()
+        .await
+        .map_err(|why| itd::network_error("read M3U8 playlist from Twitch", why))?;
+    let uri = itd::source_uri(&bytes)?;
+
+    // then the segment playlist of the source quality
+    let resp = transport
+        .send(HttpRequest::get(&uri))
+        .await
+        .map_err(|why| itd::network_error("get source M3U8 playlist", why))?;
+    let bytes = resp
+        .bytes()
+        .await
+        .map_err(|why| itd::network_error("read source M3U8 playlist", why))?;
+
+    let segments = Segments::new(conf, &vod, &uri, &bytes)?;
+    tokio::fs::create_dir_all(&segments.temp_dir)
+        .await
+        .map_err(|why| {
+            Error::io(format!(
+                "Cannot create directory `{}`, reason: \"{}\".",
+                segments.temp_dir.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+    tokio::fs::write(&segments.playlist_path, &segments.playlist)
+        .await
+        .map_err(itd::playlist_error)?;
+
+    // the segments come in a few at a time, progress is reported as each lands
+    let total_count = segments.paths.len();
+    let timeout = conf.pull.connection_timeout;
+    let downloads: Vec<_> = segments
+        .paths
+        .iter()
+        .map(|(p, u)| download_file(u, p, timeout, transport))
+        .collect();
+    let mut downloads = stream::iter(downloads).buffer_unordered(conf.pull.download_workers.max(1));
+
+    let mut done_count = 0;
+    let mut dl_size = 0;
+    while let Some(size) = downloads.next().await {
+        done_count += 1;
+        dl_size += size?;
+        progress.progress(&itd::segment_progress(
+            &vod,
+            done_count,
+            total_count,
+            dl_size,
+            start_time,
+        ));
+    }
+
+    let args = itd::stitch_args(&segments.playlist_path, &output_path);
+    let mut cmd = tokio::process::Command::from(util::ffmpeg_command(conf, &args)?);
+    util::ffmpeg_status(cmd.status().await)?;
+
+    tokio::fs::remove_dir_all(&segments.temp_dir)
+        .await
+        .map_err(itd::cleanup_error)?;
+
+    progress.finished(&vod.id, dl_size, start_time.elapsed().as_secs_f32());
+    Ok(vod)
+}
+
+pub async fn download_clip(
+    conf: &Config,
+    clip: Clip,
+    token: PlaybackAccessToken,
+    output_path: PathBuf,
+    transport: &Arc<dyn AsyncHttpTransport>,
+    noun: String,
+    progress: &dyn ProgressObserver,
+) -> Result<Clip, Error> {
+    progress.started(&noun, &clip.slug);
+
+    let start_time = Instant::now();
+    let size = download_file(
+        &itd::clip_url(&clip, token),
+        &output_path,
+        conf.pull.connection_timeout,
+        transport,
+    )
+    .await?;
+    let d32 = start_time.elapsed().as_secs_f32();
+    progress.progress(&itd::clip_progress(&clip, size, d32));
+    progress.finished(&clip.slug, size, d32);
+
+    Ok(clip)
+}
+
+// Written out chunk by chunk as it arrives, never all held in memory
+async fn download_file(
+    url: &str,
+    path: &Path,
+    timeout: usize,
+    transport: &Arc<dyn AsyncHttpTransport>,
+) -> Result<usize, Error> {
+    let mut resp = transport
+        .send(HttpRequest::get(url).timeout(Duration::from_secs(timeout as u64)))
+        .await
+        .map_err(|why| itd::network_error("get file", why))?;
+
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(itd::write_error)?;
+    let mut size = 0;
+    while let Some(chunk) = resp.body.next().await {
+        let chunk = chunk.map_err(|why| itd::network_error("read file", why))?;
+        file.write_all(&chunk).await.map_err(itd::write_error)?;
+        size += chunk.len();
+    }
+    file.flush().await.map_err(itd::write_error)?;
+
+    Ok(size)
+}
@@ -0,0 +1,426 @@
+// OAuth for YouTube, using the loopback flow for installed apps.
+// https://developers.google.com/identity/protocols/oauth2/native-app
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::blocking::Client;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::util::create_dir;
+
+const SCOPES: &str =
+    "https://www.googleapis.com/auth/youtube.upload https://www.googleapis.com/auth/youtube.force-ssl";
+
+// Refresh a little early, so a token doesn't run out between checking and using it
+const EXPIRY_MARGIN: i64 = 60;
+
+// The client secret file as downloaded from the Google Cloud console
+#[derive(Debug, Deserialize)]
+struct ClientFile {
+    installed: ClientSecret,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClientSecret {
+    pub client_id: String,
+    pub client_secret: String,
+    pub auth_uri: String,
+    pub token_uri: String,
+    #[serde(default = "default_revoke_uri")]
+    pub revoke_uri: String,
+}
+
+fn default_revoke_uri() -> String {
+    "https://oauth2.googleapis.com/revoke".to_owned()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct YouTubeSession {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    // Unix timestamp in seconds of when the access token runs out
+    pub expires_at: Option<i64>,
+}
+// Tokens never end up in logs by accident
+impl std::fmt::Debug for YouTubeSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("YouTubeSession")
+            .field("access_token", &"<redacted>")
+            .field("refresh_token", &self.refresh_token.as_ref().map(|_| "<redacted>"))
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+impl YouTubeSession {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let s = fs::read_to_string(path).map_err(|why| {
+            Error::auth(format!(
+                "Failed to read YouTube session at `{}`, have you run `vodbot login youtube`? Reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+
+        serde_json::from_str(&s).map_err(|why| {
+            Error::parse(format!(
+                "Failed to parse YouTube session at `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })
+    }
+
+    // Only the owner gets to read the session, it's as good as a password
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(p) = path.parent() {
+            create_dir(p)?;
+        }
+
+        let mut opts = fs::OpenOptions::new();
+        opts.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            opts.mode(0o600);
+        }
+
+        let map_err = |why: std::io::Error| {
+            Error::io(format!(
+                "Failed to save YouTube session to `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        };
+        let mut file = opts.open(path).map_err(map_err)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o600))
+                .map_err(map_err)?;
+        }
+        file.write_all(serde_json::to_string(self).unwrap().as_bytes())
+            .map_err(map_err)
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|f| chrono::Utc::now().timestamp() + EXPIRY_MARGIN >= f)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<i64>,
+    refresh_token: Option<String>,
+}
+
+// Load the client secret, fetching it from `client_url` the first time
+pub fn load_client(conf: &Config) -> Result<ClientSecret, Error> {
+    let path = &conf.upload.client_path;
+    if !path.exists() {
+        println!("Downloading YouTube client from `{}` ...", conf.upload.client_url);
+        let body = Client::new()
+            .get(&conf.upload.client_url)
+            .send()
+            .and_then(|f| f.error_for_status())
+            .and_then(|f| f.text())
+            .map_err(|why| {
+                Error::network(format!(
+                    "Failed to download YouTube client, reason: \"{}\".",
+                    why
+                ))
+                .with_source(why)
+            })?;
+        if let Some(p) = path.parent() {
+            create_dir(p)?;
+        }
+        fs::write(path, body).map_err(|why| {
+            Error::io(format!(
+                "Failed to save YouTube client to `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+    }
+
+    let s = fs::read_to_string(path).map_err(|why| {
+        Error::io(format!(
+            "Failed to read YouTube client at `{}`, reason: \"{}\".",
+            path.display(),
+            why
+        ))
+        .with_source(why)
+    })?;
+    serde_json::from_str::<ClientFile>(&s)
+        .map(|f| f.installed)
+        .map_err(|why| {
+            Error::parse(format!(
+                "Failed to parse YouTube client at `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })
+}
+
+// Run the whole consent flow: listen on the loopback port, have the user open
+// the consent page, catch the redirect and trade the code in for tokens.
+pub fn login(conf: &Config) -> Result<YouTubeSession, Error> {
+    let client = load_client(conf)?;
+
+    let listener = TcpListener::bind(("127.0.0.1", conf.upload.oauth_port)).map_err(|why| {
+        Error::io(format!(
+            "Failed to listen on port {}, reason: \"{}\".",
+            conf.upload.oauth_port, why
+        ))
+        .with_source(why)
+    })?;
+    let redirect_uri = format!("http://127.0.0.1:{}", conf.upload.oauth_port);
+
+    let state = random_string(32);
+    let verifier = random_string(64);
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+    let url = Url::parse_with_params(
+        &client.auth_uri,
+        &[
+            ("client_id", client.client_id.as_str()),
+            ("redirect_uri", &redirect_uri),
+            ("response_type", "code"),
+            ("scope", SCOPES),
+            ("access_type", "offline"),
+            ("prompt", "consent"),
+            ("state", &state),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|why| {
+        Error::parse(format!(
+            "Invalid auth URI in YouTube client, reason: \"{}\".",
+            why
+        ))
+        .with_source(why)
+    })?;
+
+    println!("Open this URL in your browser to log in to YouTube:\n\n{}\n", url);
+    println!("Waiting for the redirect on port {} ...", conf.upload.oauth_port);
+
+    let code = wait_for_code(&listener, &state)?;
+
+    let resp = request_token(
+        &client,
+        &[
+            ("code", code.as_str()),
+            ("client_id", &client.client_id),
+            ("client_secret", &client.client_secret),
+            ("redirect_uri", &redirect_uri),
+            ("grant_type", "authorization_code"),
+            ("code_verifier", &verifier),
+        ],
+    )?;
+
+    let session = YouTubeSession {
+        access_token: resp.access_token,
+        refresh_token: resp.refresh_token,
+        expires_at: resp.expires_in.map(|f| chrono::Utc::now().timestamp() + f),
+    };
+    session.save(&conf.upload.session_path)?;
+
+    Ok(session)
+}
+
+// Load the saved session, refreshing (and saving) it if the access token ran out
+pub fn authorize(conf: &Config) -> Result<YouTubeSession, Error> {
+    let session = YouTubeSession::load(&conf.upload.session_path)?;
+    if !session.is_expired() {
+        return Ok(session);
+    }
+
+    refresh_session(conf, session)
+}
+
+// Refresh the saved session even if it doesn't look expired yet, for when
+// YouTube turns the access token down anyway
+pub fn refresh(conf: &Config) -> Result<YouTubeSession, Error> {
+    refresh_session(conf, YouTubeSession::load(&conf.upload.session_path)?)
+}
+
+fn refresh_session(conf: &Config, mut session: YouTubeSession) -> Result<YouTubeSession, Error> {
+    let refresh_token = session.refresh_token.clone().ok_or_else(|| {
+        Error::auth(
+            "YouTube session expired and cannot be refreshed, run `vodbot login youtube`."
+                .to_owned(),
+        )
+    })?;
+
+    log::debug!("refreshing youtube access token");
+    let client = load_client(conf)?;
+    let resp = request_token(
+        &client,
+        &[
+            ("refresh_token", refresh_token.as_str()),
+            ("client_id", &client.client_id),
+            ("client_secret", &client.client_secret),
+            ("grant_type", "refresh_token"),
+        ],
+    )?;
+
+    session.access_token = resp.access_token;
+    session.expires_at = resp.expires_in.map(|f| chrono::Utc::now().timestamp() + f);
+    if resp.refresh_token.is_some() {
+        session.refresh_token = resp.refresh_token;
+    }
+    session.save(&conf.upload.session_path)?;
+
+    Ok(session)
+}
+
+// Revoke the session with Google, then forget about it locally
+pub fn logout(conf: &Config) -> Result<(), Error> {
+    let path = &conf.upload.session_path;
+    let session = YouTubeSession::load(path)?;
+    let client = load_client(conf)?;
+
+    // revoking the refresh token also revokes every access token made from it
+    let token = session.refresh_token.unwrap_or(session.access_token);
+    let resp = Client::new()
+        .post(&client.revoke_uri)
+        .form(&[("token", token)])
+        .send()
+        .map_err(|why| {
+            Error::network(format!(
+                "Failed to revoke YouTube session, reason: \"{}\".",
+                why
+            ))
+            .with_source(why)
+        })?;
+    if !resp.status().is_success() {
+        // an already revoked or expired token is no reason to keep the file around
+        log::warn!("revoking youtube session returned {}", resp.status());
+    }
+
+    fs::remove_file(path).map_err(|why| {
+        Error::io(format!(
+            "Failed to remove YouTube session at `{}`, reason: \"{}\".",
+            path.display(),
+            why
+        ))
+        .with_source(why)
+    })
+}
+
+fn wait_for_code(listener: &TcpListener, state: &str) -> Result<String, Error> {
+    loop {
+        let (mut stream, _) = listener.accept().map_err(|why| {
+            Error::io(format!(
+                "Failed to accept redirect connection, reason: \"{}\".",
+                why
+            ))
+            .with_source(why)
+        })?;
+
+        let mut line = String::new();
+        let _ = BufReader::new(&stream).read_line(&mut line);
+
+        // GET /?state=...&code=... HTTP/1.1
+        let target = line.split_whitespace().nth(1).unwrap_or("/");
+        let url = Url::parse(&format!("http://localhost{}", target)).ok();
+        let param = |k: &str| {
+            url.as_ref().and_then(|u| {
+                u.query_pairs()
+                    .find(|(n, _)| n == k)
+                    .map(|(_, v)| v.into_owned())
+            })
+        };
+
+        // browsers like to ask for favicons and such, ignore anything without a state
+        let got_state = match param("state") {
+            Some(s) => s,
+            None => {
+                let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+                continue;
+            }
+        };
+
+        let (body, result) = if got_state != state {
+            (
+                "Login failed, the state did not match. You can close this window.",
+                Err(Error::auth("OAuth state mismatch, refusing the login.")),
+            )
+        } else if let Some(code) = param("code") {
+            ("Logged in to VodBot! You can close this window.", Ok(code))
+        } else {
+            (
+                "Login failed. You can close this window.",
+                Err(Error::auth(format!(
+                    "Login was denied, reason: \"{}\".",
+                    param("error").unwrap_or_default()
+                ))),
+            )
+        };
+
+        let _ = stream.write_all(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        );
+        return result;
+    }
+}
+
+fn request_token(client: &ClientSecret, form: &[(&str, &str)]) -> Result<TokenResponse, Error> {
+    let resp = Client::new()
+        .post(&client.token_uri)
+        .form(form)
+        .send()
+        .map_err(|why| {
+            Error::network(format!(
+                "Failed to reach the token endpoint, reason: \"{}\".",
+                why
+            ))
+            .with_source(why)
+        })?;
+
+    if !resp.status().is_success() {
+        return Err(Error::network(format!(
+            "Token endpoint returned {}: \"{}\".",
+            resp.status(),
+            resp.text().unwrap_or_default()
+        )));
+    }
+
+    resp.json().map_err(|why| {
+        Error::parse(format!(
+            "Failed to parse token response, reason: \"{}\".",
+            why
+        ))
+        .with_source(why)
+    })
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
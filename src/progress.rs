@@ -0,0 +1,35 @@
+// Download progress, handed to an observer instead of printed straight to the
+// terminal, so the CLI can draw progress bars and library users can send it
+// to their own UI or metrics.
+
+// How far along a download is, after a part of it (a segment of a vod, or a
+// whole clip) finishes. Uploads report chunks the same way.
+#[derive(Debug, Clone)]
+pub struct DownloadProgress {
+    pub id: String,
+    pub parts_done: usize,
+    pub parts_total: usize,
+    pub bytes: usize,
+    pub estimated_bytes: usize,
+    pub bytes_per_second: usize,
+    pub seconds_left: f32,
+}
+
+// Everything has a default that does nothing, so observers only need to pick
+// out the events they care about. Downloads can call these from worker threads.
+pub trait ProgressObserver: Send + Sync {
+    // A download is starting, `noun` being what it is, like Vod or Clip
+    fn started(&self, _noun: &str, _id: &str) {}
+
+    fn progress(&self, _progress: &DownloadProgress) {}
+
+    // The file is written and ready, `bytes` downloaded over `seconds`
+    fn finished(&self, _id: &str, _bytes: usize, _seconds: f32) {}
+}
+
+// A closure only hears about progress, not downloads starting or finishing
+impl<F: Fn(&DownloadProgress) + Send + Sync> ProgressObserver for F {
+    fn progress(&self, progress: &DownloadProgress) {
+        self(progress)
+    }
+}
@@ -0,0 +1,140 @@
+// Template strings, for turning VodBot data into paths and text
+// Placeholders look like `{name}` or `{name:spec}`, and `{{`/`}}` escape braces.
+
+use std::path::PathBuf;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::DateTime;
+
+use crate::error::Error;
+
+// Characters that are not allowed in a path component on at least one platform.
+const UNSAFE_PATH_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+pub fn render(
+    template: &str,
+    lookup: impl Fn(&str, Option<&str>) -> Option<String>,
+) -> Result<String, Error> {
+    render_with(template, |name, spec| {
+        lookup(name, spec).ok_or_else(|| unknown_placeholder(template, name))
+    })
+}
+
+pub fn render_path(
+    template: &str,
+    lookup: impl Fn(&str, Option<&str>) -> Option<String>,
+) -> Result<PathBuf, Error> {
+    // Values are sanitized on their own, so a title can never introduce a new
+    // directory. The only separators that survive are the ones written in the
+    // template itself, including those in a spec like `{created_at:%Y/%m}`.
+    let s = render_with(template, |name, spec| {
+        let v = match spec {
+            Some(spec) => spec
+                .split('/')
+                .map(|s| lookup(name, Some(s)).map(|v| sanitize_component(&v)))
+                .collect::<Option<Vec<_>>>()
+                .map(|v| v.join("/")),
+            None => lookup(name, None).map(|v| sanitize_component(&v)),
+        };
+        v.ok_or_else(|| unknown_placeholder(template, name))
+    })?;
+
+    Ok(s.split('/')
+        .filter(|f| !f.is_empty())
+        .map(sanitize_component)
+        .collect())
+}
+
+// Make text safe to put in a template as-is, like a title from Twitch
+pub fn escape(s: &str) -> String {
+    s.replace('{', "{{").replace('}', "}}")
+}
+
+pub fn sanitize_component(s: &str) -> String {
+    let s: String = s
+        .chars()
+        .map(|c| {
+            if c.is_control() || UNSAFE_PATH_CHARS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    // Windows does not like trailing dots or spaces, and `.`/`..` would walk the tree.
+    let s = s.trim().trim_end_matches('.').to_owned();
+    if s.is_empty() {
+        "_".to_owned()
+    } else {
+        s
+    }
+}
+
+pub fn format_timestamp(timestamp: &str, spec: Option<&str>) -> Option<String> {
+    let spec = match spec {
+        Some(s) => s,
+        None => return Some(timestamp.to_owned()),
+    };
+
+    if StrftimeItems::new(spec).any(|f| f == Item::Error) {
+        log::warn!("invalid timestamp format `{}`", spec);
+        return None;
+    }
+
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|f| f.format(spec).to_string())
+}
+
+fn render_with(
+    template: &str,
+    mut lookup: impl FnMut(&str, Option<&str>) -> Result<String, Error>,
+) -> Result<String, Error> {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(malformed(template)),
+                    }
+                }
+
+                let (name, spec) = match placeholder.split_once(':') {
+                    Some((n, s)) => (n.trim(), Some(s)),
+                    None => (placeholder.trim(), None),
+                };
+                out.push_str(&lookup(name, spec)?);
+            }
+            '}' => return Err(malformed(template)),
+            c => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+fn unknown_placeholder(template: &str, name: &str) -> Error {
+    Error::input(format!(
+        "Unknown or invalid placeholder `{{{}}}` in template `{}`.",
+        name, template
+    ))
+}
+
+fn malformed(template: &str) -> Error {
+    Error::input(format!("Unbalanced braces in template `{}`.", template))
+}
@@ -0,0 +1,183 @@
+// Thumbnail rendering, compositing a video frame with head and game images
+// and some text, laid out by the thumbnail section of the config.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::config::{Config, ConfigThumbnailIcon, ConfigThumbnailPosition};
+use crate::error::Error;
+use crate::timestamp;
+use crate::util::{create_dir, ffmpeg_filter_path, format_duration, run_ffmpeg};
+use crate::vodbot_api::StageData;
+
+// Render a stage's thumbnail to a PNG at `output`. Only ffmpeg is used, so
+// everything is drawn on the CPU.
+pub fn render(conf: &Config, stage: &StageData, output: &Path) -> Result<(), Error> {
+    let tn = stage
+        .thumbnail
+        .as_ref()
+        .ok_or_else(|| Error::input(format!("Stage `{}` has no thumbnail data.", stage.id)))?;
+    let layout = &conf.thumbnail;
+
+    let slice = stage.slices.get(tn.video_slice_idx).ok_or_else(|| {
+        Error::input(format!(
+            "Thumbnail slice index {} is out of range, stage `{}` has {} slices.",
+            tn.video_slice_idx,
+            stage.id,
+            stage.slices.len()
+        ))
+    })?;
+
+    // the timestamp is relative to the start of the slice, not the whole video
+    let ss = thumbnail_time(&slice.ss)?;
+    let offset = thumbnail_time(&tn.timestamp)?;
+    let frame_at = format_duration(ss + offset);
+
+    let mut args: Vec<String> = vec![
+        "-ss".to_owned(),
+        frame_at,
+        "-i".to_owned(),
+        slice.filepath.to_str().unwrap().to_owned(),
+    ];
+    let mut filters = vec![format!(
+        "[0:v]scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},setsar=1[v0]",
+        w = layout.width,
+        h = layout.height
+    )];
+    let mut input = 0;
+
+    if let Some(o) = &layout.overlay {
+        input += 1;
+        args.extend(image_input(conf, o));
+        filters.push(format!(
+            "[{i}:v]scale={}:{}[img{i}];[v{p}][img{i}]overlay=0:0[v{i}]",
+            layout.width,
+            layout.height,
+            i = input,
+            p = input - 1
+        ));
+    }
+
+    if tn.heads.len() > layout.head_positions.len() {
+        log::warn!(
+            "stage `{}` has {} heads but only {} head positions, extra heads are left out",
+            stage.id,
+            tn.heads.len(),
+            layout.head_positions.len()
+        );
+    }
+    let mut icons = Vec::new();
+    for (h, pos) in tn.heads.iter().zip(&layout.head_positions) {
+        icons.push((find_icon(&layout.heads, "head", h)?, pos));
+    }
+    if !tn.game.is_empty() {
+        icons.push((
+            find_icon(&layout.games, "game", &tn.game)?,
+            &layout.game_position,
+        ));
+    }
+
+    for (icon, pos) in icons {
+        input += 1;
+        args.extend(image_input(conf, &icon.filename));
+        filters.push(icon_filter(input, icon, pos));
+    }
+
+    let mut last = format!("v{}", input);
+    let text_path = conf
+        .directories
+        .temp
+        .join(format!("thumbnail_{}.txt", stage.id));
+    if !tn.text.is_empty() {
+        // drawtext reads from a file, so the text never needs filter escaping
+        create_dir(&conf.directories.temp)?;
+        fs::write(&text_path, &tn.text).map_err(|why| {
+            Error::io(format!(
+                "Failed to write thumbnail text, reason: \"{}\".",
+                why
+            ))
+            .with_source(why)
+        })?;
+
+        let pos = &layout.text_position;
+        let mut draw = format!(
+            "[{}]drawtext=textfile={}:x={}:y={}:fontsize={}:fontcolor={}:borderw={}:bordercolor=black",
+            last,
+            ffmpeg_filter_path(&text_path),
+            pos.x,
+            pos.y,
+            (layout.text_size as f32 * pos.s).round() as u32,
+            layout.text_color,
+            layout.text_border
+        );
+        if let Some(f) = &layout.font {
+            draw += &format!(":fontfile={}", ffmpeg_filter_path(f));
+        }
+        draw += "[vtext]";
+        filters.push(draw);
+        last = "vtext".to_owned();
+    }
+
+    if let Some(p) = output.parent() {
+        create_dir(p)?;
+    }
+    args.extend([
+        "-filter_complex".to_owned(),
+        filters.join(";"),
+        "-map".to_owned(),
+        format!("[{}]", last),
+        "-frames:v".to_owned(),
+        "1".to_owned(),
+        "-f".to_owned(),
+        "image2".to_owned(),
+        "-c:v".to_owned(),
+        "png".to_owned(),
+        output.to_str().unwrap().to_owned(),
+    ]);
+
+    let result = run_ffmpeg(conf, &args.iter().map(|f| f.as_str()).collect::<Vec<_>>());
+    let _ = fs::remove_file(&text_path);
+    result
+}
+
+fn thumbnail_time(s: &str) -> Result<usize, Error> {
+    timestamp::parse(s).ok_or_else(|| Error::input(format!("Invalid thumbnail timestamp `{}`.", s)))
+}
+
+fn find_icon<'a>(
+    icons: &'a HashMap<String, ConfigThumbnailIcon>,
+    what: &str,
+    name: &str,
+) -> Result<&'a ConfigThumbnailIcon, Error> {
+    icons.get(name).ok_or_else(|| {
+        Error::not_found(format!(
+            "No {} image named `{}` in the thumbnail config.",
+            what, name
+        ))
+    })
+}
+
+fn image_input(conf: &Config, filename: &str) -> [String; 2] {
+    [
+        "-i".to_owned(),
+        conf.directories
+            .thumbnail
+            .join(filename)
+            .to_str()
+            .unwrap()
+            .to_owned(),
+    ]
+}
+
+// The icon's own scale and offset apply on top of the slot it's placed in
+fn icon_filter(i: usize, icon: &ConfigThumbnailIcon, pos: &ConfigThumbnailPosition) -> String {
+    format!(
+        "[{i}:v]scale=iw*{s}:ih*{s}[img{i}];[v{p}][img{i}]overlay={x}:{y}[v{i}]",
+        s = icon.s * pos.s,
+        x = pos.x + icon.ox,
+        y = pos.y + icon.oy,
+        i = i,
+        p = i - 1
+    )
+}
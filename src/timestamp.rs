@@ -0,0 +1,165 @@
+// Timestamps, for parsing and checking where stage slices start and end.
+//
+// A time can be written as `H:MM:SS`, `MM:SS`, plain seconds or `1h2m3s`.
+// Slice bounds can also be a chapter of the vod, `chapter:N` (counting from
+// 1), and the end of a slice can be relative to its start, like `+30m`.
+
+use crate::error::Error;
+use crate::util::format_duration;
+use crate::vodbot_api::VodChapter;
+
+enum SliceBound {
+    At(usize),
+    Chapter(usize),
+    After(usize),
+}
+
+// Parse a time into seconds
+pub fn parse(s: &str) -> Option<usize> {
+    let s = s.trim();
+    if s.is_empty() {
+        None
+    } else if s.ends_with(['h', 'm', 's']) {
+        parse_units(s)
+    } else {
+        parse_clock(s)
+    }
+}
+
+// Parse a bound of a slice already saved in a stage, which was resolved to a
+// plain time when the stage was made
+pub fn slice_time(s: &str) -> Result<usize, Error> {
+    parse(s).ok_or_else(|| Error::input(format!("Invalid timestamp `{}` in stage slice.", s)))
+}
+
+// Work out a slice's start and end in seconds, checking them against the
+// video they're cut from. Missing bounds mean the start or end of the video.
+pub fn resolve_slice(
+    ss: Option<&str>,
+    to: Option<&str>,
+    chapters: &[VodChapter],
+    duration: usize,
+) -> Result<(usize, usize), Error> {
+    let start = match ss.map(parse_bound).transpose()? {
+        None => 0,
+        Some(SliceBound::At(t)) => t,
+        Some(SliceBound::Chapter(n)) => chapter(chapters, n)?.position,
+        Some(SliceBound::After(_)) => {
+            return Err(bad_timestamp(
+                ss.unwrap(),
+                "only the end of a slice can be relative",
+            ))
+        }
+    };
+    let end = match to.map(parse_bound).transpose()? {
+        None => duration,
+        Some(SliceBound::At(t)) => t,
+        Some(SliceBound::Chapter(n)) => {
+            let c = chapter(chapters, n)?;
+            c.position.saturating_add(c.duration)
+        }
+        Some(SliceBound::After(d)) => start
+            .checked_add(d)
+            .ok_or_else(|| bad_timestamp(to.unwrap(), "the length is too long"))?,
+    };
+
+    if end <= start {
+        return Err(Error::input(format!(
+            "Slice ends at {} before it starts at {}.",
+            format_duration(end),
+            format_duration(start)
+        )));
+    }
+    if end > duration {
+        return Err(Error::input(format!(
+            "Slice ends at {}, past the end of the video at {}.",
+            format_duration(end),
+            format_duration(duration)
+        )));
+    }
+
+    Ok((start, end))
+}
+
+fn parse_bound(s: &str) -> Result<SliceBound, Error> {
+    let s = s.trim();
+    let bound = if let Some(n) = s.strip_prefix("chapter:") {
+        n.trim().parse().ok().map(SliceBound::Chapter)
+    } else if let Some(t) = s.strip_prefix('+') {
+        parse(t).map(SliceBound::After)
+    } else {
+        parse(s).map(SliceBound::At)
+    };
+
+    bound.ok_or_else(|| {
+        bad_timestamp(
+            s,
+            "expected `H:MM:SS`, `MM:SS`, seconds, `1h2m3s`, `chapter:N` or `+` and a length",
+        )
+    })
+}
+
+fn chapter(chapters: &[VodChapter], n: usize) -> Result<&VodChapter, Error> {
+    n.checked_sub(1)
+        .and_then(|i| chapters.get(i))
+        .ok_or_else(|| {
+            Error::input(format!(
+                "There is no chapter {}, the video has {} chapters.",
+                n,
+                chapters.len()
+            ))
+        })
+}
+
+// `H:MM:SS`, `MM:SS` or seconds, where anything after the first field is under 60
+fn parse_clock(s: &str) -> Option<usize> {
+    let fields: Vec<&str> = s.split(':').collect();
+    if fields.len() > 3 {
+        return None;
+    }
+
+    let mut total: usize = 0;
+    for (i, f) in fields.iter().enumerate() {
+        if f.is_empty() || !f.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let n: usize = f.parse().ok()?;
+        if i > 0 && n >= 60 {
+            return None;
+        }
+        total = total.checked_mul(60)?.checked_add(n)?;
+    }
+
+    Some(total)
+}
+
+// `1h2m3s`, where each unit is optional but they have to come in that order
+fn parse_units(s: &str) -> Option<usize> {
+    let mut total: usize = 0;
+    let mut number = String::new();
+    let mut units = ['h', 'm', 's'].iter();
+
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let scale = match units.find(|u| **u == c)? {
+            'h' => 3600,
+            'm' => 60,
+            _ => 1,
+        };
+        if number.is_empty() {
+            return None;
+        }
+        total = total.checked_add(number.parse::<usize>().ok()?.checked_mul(scale)?)?;
+        number.clear();
+    }
+
+    Some(total)
+}
+
+fn bad_timestamp(s: &str, why: &str) -> Error {
+    Error::input(format!("Invalid timestamp `{}`, {}.", s, why))
+}
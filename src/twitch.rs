@@ -1,532 +1,675 @@
 // Twitch library for making specific queries given a GQLClient
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
+use crate::error::Error;
 use crate::gql::GQLClient;
+use crate::gql_query::{document, Arg, Field};
 use crate::twitch_api::{
-    TwitchClip, TwitchData, TwitchPlaybackAccessTokenToken, TwitchResponse, TwitchUser, TwitchVideo,
+    TwitchClip, TwitchConnection, TwitchData, TwitchPlaybackAccessToken,
+    TwitchPlaybackAccessTokenToken, TwitchResponse, TwitchUser, TwitchUserRoles, TwitchUserStream,
+    TwitchVideo, TwitchVideoComment, TwitchVideoMoment,
 };
-use crate::util::ExitMsg;
 use crate::vodbot_api::{ChatMessage, Clip, PlaybackAccessToken, Vod, VodChapter};
 
-use indoc::formatdoc;
-
 #[derive(Debug, Clone)]
 struct QueryMap {
     next: bool,
+    found: bool,
     id: String,
     after: String,
 }
 
-fn batched_query<T: TwitchData + for<'de> serde::Deserialize<'de>, R: Clone>(
-    query: Box<dyn Fn(String, String, String) -> String>,
-    client: &GQLClient,
-    var: &Vec<String>,
-    mut tf: Box<dyn FnMut(&GQLClient, &T, &mut Vec<R>) -> Result<(bool, String), ExitMsg>>,
-) -> Result<HashMap<String, Vec<R>>, ExitMsg> {
-    let mut queries: HashMap<String, QueryMap> = var
-        .iter()
-        .map(|f| {
-            (
-                "_".to_owned() + &f.replace("-", "_"),
-                QueryMap { next: true, id: f.clone(), after: "".to_owned() }
-            )
-        })
-        .collect();
-    let mut results: HashMap<String, Vec<R>> = var
-        .iter()
-        .map(|f| ("_".to_owned() + &f.replace("-", "_"), Vec::new()))
-        .collect();
+// Results of a batched query, keyed by the ids asked for. Ids that Twitch
+// answered with null, like renamed channels or deleted videos, are in `missing`.
+#[derive(Debug, Clone)]
+pub struct BatchResult<R> {
+    pub found: HashMap<String, Vec<R>>,
+    pub missing: Vec<String>,
+}
 
-    'queryloop: loop {
-        let q: Vec<_> = queries
-            .values()
-            .cloned()
-            .filter(|f| f.next)
-            .map(|f| query(f.id.replace("-", "_"), f.id, f.after))
+// The one result of a batch of one, or an error if Twitch didn't find it
+pub(crate) fn single<R>(mut found: HashMap<String, R>, id: &str) -> Result<R, Error> {
+    found
+        .remove(id)
+        .ok_or_else(|| Error::not_found(format!("Cannot find `{}` on Twitch.", id)))
+}
+
+// Builds the field for one id, given the cursor to page from
+type QueryFn = dyn Fn(&str, &str) -> Field + Send + Sync;
+// Adds one page of an id's answer to its results, giving back whether there's
+// another page and the cursor to it
+type TransformFn<T, R> = fn(&T, &mut Vec<R>) -> (bool, String);
+
+// What a batched query asks Twitch for each id, and how the answers turn into
+// results. The same batch is run by the blocking getters here and the async
+// ones in `nonblocking`.
+pub(crate) struct Batch<T, R> {
+    query: Box<QueryFn>,
+    transform: TransformFn<T, R>,
+}
+
+// One chunk of a batch, paging through every id in it until Twitch runs out
+pub(crate) struct Chunk<'a, T, R> {
+    batch: &'a Batch<T, R>,
+    queries: HashMap<String, QueryMap>,
+    results: HashMap<String, Vec<R>>,
+}
+impl<'a, T: TwitchData, R> Chunk<'a, T, R> {
+    pub(crate) fn new(batch: &'a Batch<T, R>, var: &[String]) -> Self {
+        // aliased by position, ids and slugs can have characters aliases can't
+        let queries: HashMap<String, QueryMap> = var
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                (
+                    format!("_{}", i),
+                    QueryMap {
+                        next: true,
+                        found: false,
+                        id: f.clone(),
+                        after: "".to_owned(),
+                    },
+                )
+            })
             .collect();
+        let results = queries.keys().map(|k| (k.clone(), Vec::new())).collect();
+
+        Self {
+            batch,
+            queries,
+            results,
+        }
+    }
 
-        log::debug!("cursors: {:?}", queries.values().collect::<Vec<_>>());
-        log::trace!("queries:\n{{\n{}\n}}", q.join("\n"));
+    // The query for every id with pages left, or None once they're all done
+    pub(crate) fn next_query(&self) -> Option<String> {
+        // sorted, so the same state always makes the same query for fixtures
+        let mut pending: Vec<_> = self.queries.iter().filter(|(_, f)| f.next).collect();
+        if pending.is_empty() {
+            return None;
+        }
+        pending.sort_by(|a, b| a.1.id.cmp(&b.1.id));
+        let q: Vec<_> = pending
+            .into_iter()
+            .map(|(alias, f)| (self.batch.query)(&f.id, &f.after).alias(alias))
+            .collect();
 
-        let j: TwitchResponse<T> = client.query(format!("{{ {} }}", q.join("\n")))?;
+        log::debug!("cursors: {:?}", self.queries.values().collect::<Vec<_>>());
+        let q = document(&q);
+        log::trace!("query: {}", q);
+        Some(q)
+    }
 
+    pub(crate) fn answer(&mut self, j: TwitchResponse<T>) {
         for (k, v) in j.data.unwrap() {
-            let q = queries.get_mut(&k).unwrap();
-            let r = results.get_mut(&k).unwrap();
-
-            if v.is_none() {
-                // FIXME: this probably isn't a great idea, and may miss some results. further testing recommended.
-                break 'queryloop;
+            let q = self.queries.get_mut(&k).unwrap();
+            let r = self.results.get_mut(&k).unwrap();
+
+            // a null alias only stops its own pagination, the rest carry on
+            match v {
+                Some(v) => {
+                    q.found = true;
+                    (q.next, q.after) = (self.batch.transform)(&v, r);
+                }
+                None => {
+                    log::debug!("{} came back null, after {:?}", q.id, q.after);
+                    q.next = false;
+                }
             }
+        }
+    }
+
+    pub(crate) fn finish(self) -> BatchResult<R> {
+        let queries = self.queries;
+        let missing: Vec<_> = queries
+            .values()
+            .filter(|f| !f.found)
+            .map(|f| f.id.clone())
+            .collect();
 
-            (q.next, q.after) = tf(&client, &v.unwrap(), r)?;
+        BatchResult {
+            found: self
+                .results
+                .into_iter()
+                .filter(|(k, _)| queries.get(k).unwrap().found)
+                .map(|(k, v)| (queries.get(&k).unwrap().id.clone(), v))
+                .collect(),
+            missing,
         }
+    }
+}
+
+// Put the results of every chunk back together
+pub(crate) fn merge<R>(
+    results: Vec<Result<BatchResult<R>, Error>>,
+) -> Result<BatchResult<R>, Error> {
+    let mut merged = BatchResult {
+        found: HashMap::new(),
+        missing: Vec::new(),
+    };
+    for r in results {
+        let r = r?;
+        merged.found.extend(r.found);
+        merged.missing.extend(r.missing);
+    }
+    merged.missing.sort();
 
-        if !queries.values().any(|f| f.next) {
-            break;
+    Ok(merged)
+}
+
+// Query every id in `var`, split into chunks of the client's batch size so no
+// one document gets too big for Twitch, with a few chunks in flight at once if
+// the client allows it.
+fn batched_query<T: TwitchData + for<'de> serde::Deserialize<'de>, R: Send>(
+    batch: Batch<T, R>,
+    client: &GQLClient,
+    var: &[String],
+) -> Result<BatchResult<R>, Error> {
+    let chunks: Vec<_> = var.chunks(client.batch_size()).collect();
+    let workers = client.parallel_batches().min(chunks.len());
+    log::debug!(
+        "querying {} ids in {} chunks, {} at a time",
+        var.len(),
+        chunks.len(),
+        workers
+    );
+
+    let mut results = Vec::new();
+    if workers <= 1 {
+        for c in &chunks {
+            results.push(query_chunk(&batch, client, c));
         }
+    } else {
+        let next = AtomicUsize::new(0);
+        let done = Mutex::new(Vec::new());
+        thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= chunks.len() {
+                        break;
+                    }
+                    let r = query_chunk(&batch, client, chunks[i]);
+                    done.lock().unwrap().push(r);
+                });
+            }
+        });
+        results = done.into_inner().unwrap();
     }
 
-    Ok(results
-        .iter()
-        .map(|(k, v)| (queries.get(k).unwrap().id.clone(), v.to_owned()))
-        .collect())
+    merge(results)
 }
 
-pub fn get_channels_videos(
+fn query_chunk<T: TwitchData + for<'de> serde::Deserialize<'de>, R>(
+    batch: &Batch<T, R>,
     client: &GQLClient,
-    user_logins: &Vec<String>,
-    video_type: String,
-) -> Result<HashMap<String, Vec<Vod>>, ExitMsg> {
-    // Get all videos from a list of channels
+    var: &[String],
+) -> Result<BatchResult<R>, Error> {
+    let mut chunk = Chunk::new(batch, var);
+    while let Some(q) = chunk.next_query() {
+        chunk.answer(client.query(q)?);
+    }
+    Ok(chunk.finish())
+}
 
-    log::debug!("getting video(s) of type {}", video_type);
+// The types Twitch takes for each kind of video. A few can be asked for at
+// once, separated by commas.
+pub(crate) const ARCHIVE: &str = "ARCHIVE";
+pub(crate) const HIGHLIGHT: &str = "HIGHLIGHT";
+pub(crate) const UPLOAD: &str = "UPLOAD";
+pub(crate) const PREMIERE: &str = "PREMIERE_UPLOAD, PAST_PREMIERE";
+
+// Videos of the given type(s), without their chapters, see `add_chapters`
+pub(crate) fn channels_videos(video_type: &str) -> Batch<TwitchUser, Vod> {
+    let types: Vec<_> = video_type
+        .split(',')
+        .map(|t| Arg::Enum(t.trim().to_owned()))
+        .collect();
 
-    batched_query::<TwitchUser, Vod>(
-        Box::new(move |alias, id, after| {
-            formatdoc! {"
-                _{}: user( login: \"{}\" ) {{
-                    id login displayName
-                    videos( after: \"{}\", first: 100, sort: TIME, types: [{}] ) {{
-                        pageInfo {{ hasNextPage }}
-                        edges {{ cursor node {{
-                            id title createdAt status
-                            broadcastType lengthSeconds
-                            game {{ id name }}
-                }}  }}  }}  }}",
-                alias, id, after, video_type
-            }
+    Batch {
+        query: Box::new(move |id, after| {
+            Field::new("user")
+                .arg("login", id)
+                .of::<TwitchUser>()
+                .select(vec![Field::new("videos")
+                    .arg("after", after)
+                    .arg("first", 100)
+                    .arg("sort", Arg::Enum("TIME".to_owned()))
+                    .arg("types", Arg::List(types.clone()))
+                    .of::<TwitchConnection<TwitchVideo>>()])
         }),
-        client,
-        user_logins,
-        Box::new(|client: &GQLClient, v: &TwitchUser, r: &mut Vec<Vod>| {
+        transform: |v, r| {
             let u = v.videos.as_ref().unwrap();
             let mut after = "".to_owned();
 
-            // For each Vod, lets get it's vod chapters now too
-            let vod_ids: Vec<_> = u.edges.iter().map(|f| f.node.id.clone()).collect();
-            let chapters = get_videos_chapters(client, &vod_ids)?;
+            for s in &u.edges {
+                r.push(Vod::from_data(v, &s.node, Vec::new()));
+
+                if let Some(c) = s.cursor.to_owned() {
+                    after = c;
+                }
+            }
+
+            (u.page_info.has_next_page, after)
+        },
+    }
+}
+
+// Every video id in a batch of videos, to get their chapters with
+pub(crate) fn vod_ids(vods: &BatchResult<Vod>) -> Vec<String> {
+    vods.found
+        .values()
+        .flatten()
+        .map(|v| v.id.clone())
+        .collect()
+}
+
+pub(crate) fn add_chapters(vods: &mut BatchResult<Vod>, mut chapters: BatchResult<VodChapter>) {
+    for v in vods.found.values_mut().flatten() {
+        v.chapters = chapters.found.remove(&v.id).unwrap_or_default();
+    }
+}
+
+pub(crate) fn channels_clips() -> Batch<TwitchUser, Clip> {
+    Batch {
+        query: Box::new(|id, after| {
+            Field::new("user")
+                .arg("login", id)
+                .of::<TwitchUser>()
+                .select(vec![Field::new("clips")
+                    .arg("after", after)
+                    .arg("first", 100)
+                    .arg(
+                        "criteria",
+                        Arg::Object(vec![
+                            ("period", Arg::Enum("ALL_TIME".to_owned())),
+                            ("sort", Arg::Enum("VIEWS_DESC".to_owned())),
+                        ]),
+                    )
+                    .of::<TwitchConnection<TwitchClip>>()])
+        }),
+        transform: |v, r| {
+            let u = v.clips.as_ref().unwrap();
+            let mut after = "".to_owned();
 
             for s in &u.edges {
-                let c = chapters.get(&s.node.id).unwrap().to_owned();
-                r.push(Vod::from_data(v, &s.node, c));
+                r.push(Clip::from_data(v, &s.node));
 
                 if let Some(c) = s.cursor.to_owned() {
                     after = c;
                 }
             }
 
-            Ok((u.page_info.has_next_page, after))
+            (u.page_info.has_next_page, after)
+        },
+    }
+}
+
+pub(crate) fn videos_comments() -> Batch<TwitchVideo, ChatMessage> {
+    Batch {
+        query: Box::new(|id, after| {
+            Field::new("video")
+                .arg("id", id)
+                .of::<TwitchVideo>()
+                .select(vec![Field::new("comments")
+                    .arg("after", after)
+                    .arg("contentOffsetSeconds", 0)
+                    .of::<TwitchConnection<TwitchVideoComment>>()])
         }),
-    )
+        transform: |v, r| {
+            let u = v.comments.as_ref().unwrap();
+            let mut after = "".to_owned();
+
+            for s in &u.edges {
+                r.push(ChatMessage::from_data(&s.node));
+
+                if let Some(c) = s.cursor.to_owned() {
+                    after = c;
+                }
+            }
+
+            (u.page_info.has_next_page, after)
+        },
+    }
+}
+
+pub(crate) fn videos_chapters() -> Batch<TwitchVideo, VodChapter> {
+    Batch {
+        query: Box::new(|id, after| {
+            Field::new("video")
+                .arg("id", id)
+                .of::<TwitchVideo>()
+                .select(vec![Field::new("moments")
+                    .arg("after", after)
+                    .arg("first", 100)
+                    .arg(
+                        "momentRequestType",
+                        Arg::Enum("VIDEO_CHAPTER_MARKERS".to_owned()),
+                    )
+                    .of::<TwitchConnection<TwitchVideoMoment>>()])
+        }),
+        transform: |v, r| {
+            let u = v.moments.as_ref().unwrap();
+            let mut after = "".to_owned();
+
+            for s in &u.edges {
+                r.push(VodChapter::from_data(&s.node));
+
+                if let Some(c) = s.cursor.to_owned() {
+                    after = c;
+                }
+            }
+
+            (u.page_info.has_next_page, after)
+        },
+    }
+}
+
+// Tokens are asked for as the web player, the same for videos and clips
+fn playback_access_token() -> Field {
+    Field::new("playbackAccessToken")
+        .arg(
+            "params",
+            Arg::Object(vec![
+                ("platform", "web".into()),
+                ("playerType", "site".into()),
+                ("playerBackend", "mediaplayer".into()),
+            ]),
+        )
+        .of::<TwitchPlaybackAccessToken>()
+}
+
+pub(crate) fn videos_playback_access_tokens(
+) -> Batch<TwitchPlaybackAccessTokenToken, PlaybackAccessToken> {
+    Batch {
+        query: Box::new(|id, _| {
+            Field::new("video")
+                .arg("id", id)
+                .select(vec![playback_access_token()])
+        }),
+        transform: token_transform,
+    }
+}
+
+pub(crate) fn clips_playback_access_tokens(
+) -> Batch<TwitchPlaybackAccessTokenToken, PlaybackAccessToken> {
+    Batch {
+        query: Box::new(|id, _| {
+            Field::new("clip")
+                .arg("slug", id)
+                .select(vec![playback_access_token()])
+        }),
+        transform: token_transform,
+    }
+}
+
+fn token_transform(
+    v: &TwitchPlaybackAccessTokenToken,
+    r: &mut Vec<PlaybackAccessToken>,
+) -> (bool, String) {
+    r.push(PlaybackAccessToken::from_data(&v.playback_access_token));
+    (false, "".to_owned())
+}
+
+// One token per id, leaving out the ones Twitch had none for
+pub(crate) fn tokens(j: BatchResult<PlaybackAccessToken>) -> HashMap<String, PlaybackAccessToken> {
+    j.found
+        .into_iter()
+        .filter_map(|(k, mut v)| v.pop().map(|t| (k, t)))
+        .collect()
+}
+
+pub fn get_channels_videos(
+    client: &GQLClient,
+    user_logins: &[String],
+    video_type: String,
+) -> Result<BatchResult<Vod>, Error> {
+    // Get all videos from a list of channels
+
+    log::debug!("getting video(s) of type {}", video_type);
+
+    let mut vods = batched_query(channels_videos(&video_type), client, user_logins)?;
+
+    // For each Vod, lets get it's vod chapters now too
+    let chapters = get_videos_chapters(client, &vod_ids(&vods))?;
+    add_chapters(&mut vods, chapters);
+
+    Ok(vods)
 }
 
 pub fn get_channels_videos_archive(
     client: &GQLClient,
-    user_logins: &Vec<String>,
-) -> Result<HashMap<String, Vec<Vod>>, ExitMsg> {
-    get_channels_videos(client, user_logins, "ARCHIVE".to_owned())
+    user_logins: &[String],
+) -> Result<BatchResult<Vod>, Error> {
+    get_channels_videos(client, user_logins, ARCHIVE.to_owned())
 }
 
 pub fn get_channels_videos_highlight(
     client: &GQLClient,
-    user_logins: &Vec<String>,
-) -> Result<HashMap<String, Vec<Vod>>, ExitMsg> {
-    get_channels_videos(client, user_logins, "HIGHLIGHT".to_owned())
+    user_logins: &[String],
+) -> Result<BatchResult<Vod>, Error> {
+    get_channels_videos(client, user_logins, HIGHLIGHT.to_owned())
 }
 
 pub fn get_channels_videos_upload(
     client: &GQLClient,
-    user_logins: &Vec<String>,
-) -> Result<HashMap<String, Vec<Vod>>, ExitMsg> {
-    get_channels_videos(client, user_logins, "UPLOAD".to_owned())
+    user_logins: &[String],
+) -> Result<BatchResult<Vod>, Error> {
+    get_channels_videos(client, user_logins, UPLOAD.to_owned())
 }
 
 pub fn get_channels_videos_premiere(
     client: &GQLClient,
-    user_logins: &Vec<String>,
-) -> Result<HashMap<String, Vec<Vod>>, ExitMsg> {
-    get_channels_videos(
-        client,
-        user_logins,
-        "PREMIERE_UPLOAD, PAST_PREMIERE".to_owned(),
-    )
+    user_logins: &[String],
+) -> Result<BatchResult<Vod>, Error> {
+    get_channels_videos(client, user_logins, PREMIERE.to_owned())
 }
 
 pub fn get_channel_videos(
     client: &GQLClient,
     user_login: String,
     video_type: String,
-) -> Result<Vec<Vod>, ExitMsg> {
-    Ok(get_channels_videos(client, &vec![user_login], video_type)?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+) -> Result<Vec<Vod>, Error> {
+    single(
+        get_channels_videos(client, std::slice::from_ref(&user_login), video_type)?.found,
+        &user_login,
+    )
 }
 
 pub fn get_channel_videos_archive(
     client: &GQLClient,
     user_login: String,
-) -> Result<Vec<Vod>, ExitMsg> {
-    Ok(get_channels_videos_archive(client, &vec![user_login])?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+) -> Result<Vec<Vod>, Error> {
+    single(
+        get_channels_videos_archive(client, std::slice::from_ref(&user_login))?.found,
+        &user_login,
+    )
 }
 
 pub fn get_channel_videos_highlight(
     client: &GQLClient,
     user_login: String,
-) -> Result<Vec<Vod>, ExitMsg> {
-    Ok(get_channels_videos_highlight(client, &vec![user_login])?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+) -> Result<Vec<Vod>, Error> {
+    single(
+        get_channels_videos_highlight(client, std::slice::from_ref(&user_login))?.found,
+        &user_login,
+    )
 }
 
 pub fn get_channel_videos_upload(
     client: &GQLClient,
     user_login: String,
-) -> Result<Vec<Vod>, ExitMsg> {
-    Ok(get_channels_videos_upload(client, &vec![user_login])?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+) -> Result<Vec<Vod>, Error> {
+    single(
+        get_channels_videos_upload(client, std::slice::from_ref(&user_login))?.found,
+        &user_login,
+    )
 }
 
 pub fn get_channel_videos_premiere(
     client: &GQLClient,
     user_login: String,
-) -> Result<Vec<Vod>, ExitMsg> {
-    Ok(get_channels_videos_premiere(client, &vec![user_login])?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+) -> Result<Vec<Vod>, Error> {
+    single(
+        get_channels_videos_premiere(client, std::slice::from_ref(&user_login))?.found,
+        &user_login,
+    )
 }
 
 pub fn get_channels_clips(
     client: &GQLClient,
-    user_logins: &Vec<String>,
-) -> Result<HashMap<String, Vec<Clip>>, ExitMsg> {
+    user_logins: &[String],
+) -> Result<BatchResult<Clip>, Error> {
     // Get all clips from a list of channels
 
     log::debug!("getting clip(s)");
 
-    batched_query::<TwitchUser, Clip>(
-        Box::new(|alias, id, after| {
-            formatdoc! {"
-                _{}: user( login: \"{}\" ) {{
-                    id login displayName
-                    clips(
-                        after: \"{}\", first: 100,
-                        criteria: {{ period: ALL_TIME, sort: VIEWS_DESC }}
-                    ) {{
-                        pageInfo {{ hasNextPage }}
-                        edges {{ cursor node {{
-                            id slug title createdAt viewCount
-                            durationSeconds videoOffsetSeconds
-                            video {{ id }}
-                            game {{ id name }}
-                            curator {{ id displayName login }}
-                            videoQualities {{ frameRate quality sourceURL }}
-                }}  }}  }}  }}",
-                alias, id, after
-            }
-        }),
-        client,
-        user_logins,
-        Box::new(|_: &GQLClient, v: &TwitchUser, r: &mut Vec<Clip>| {
-            let u = v.clips.as_ref().unwrap();
-            let mut after = "".to_owned();
-
-            for s in &u.edges {
-                r.push(Clip::from_data(v, &s.node));
-
-                if let Some(c) = s.cursor.to_owned() {
-                    after = c;
-                }
-            }
-
-            Ok((u.page_info.has_next_page, after))
-        }),
-    )
+    batched_query(channels_clips(), client, user_logins)
 }
 
-pub fn get_channel_clips(client: &GQLClient, user_login: String) -> Result<Vec<Clip>, ExitMsg> {
-    Ok(get_channels_clips(client, &vec![user_login])?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+pub fn get_channel_clips(client: &GQLClient, user_login: String) -> Result<Vec<Clip>, Error> {
+    single(
+        get_channels_clips(client, std::slice::from_ref(&user_login))?.found,
+        &user_login,
+    )
 }
 
 pub fn get_videos_comments(
     client: &GQLClient,
-    video_ids: &Vec<String>,
-) -> Result<HashMap<String, Vec<ChatMessage>>, ExitMsg> {
+    video_ids: &[String],
+) -> Result<BatchResult<ChatMessage>, Error> {
     // Get all videos from a list of channels
 
     log::debug!("getting video(s) comments");
 
-    batched_query::<TwitchVideo, ChatMessage>(
-        Box::new(|alias, id, after| {
-            formatdoc! {"
-                _{}: video( id: \"{}\" ) {{
-                    id title createdAt broadcastType status lengthSeconds
-                    comments( after: \"{}\", contentOffsetSeconds: 0 ) {{
-                        pageInfo {{ hasNextPage }}
-                        edges {{ cursor node {{
-                            contentOffsetSeconds
-                            commenter {{ displayName }}
-                            message {{ fragments {{ mention {{ displayName }} text }} userColor }}
-                }}  }}  }}  }}",
-                alias, id, after
-            }
-        }),
-        client,
-        video_ids,
-        Box::new(|_: &GQLClient, v: &TwitchVideo, r: &mut Vec<ChatMessage>| {
-            let u = v.comments.as_ref().unwrap();
-            let mut after = "".to_owned();
-
-            for s in &u.edges {
-                r.push(ChatMessage::from_data(&s.node));
-
-                if let Some(c) = s.cursor.to_owned() {
-                    after = c;
-                }
-            }
-
-            Ok((u.page_info.has_next_page, after))
-        }),
-    )
+    batched_query(videos_comments(), client, video_ids)
 }
 
-pub fn get_video_comments(
-    client: &GQLClient,
-    video_id: String,
-) -> Result<Vec<ChatMessage>, ExitMsg> {
-    Ok(get_videos_comments(client, &vec![video_id])?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+pub fn get_video_comments(client: &GQLClient, video_id: String) -> Result<Vec<ChatMessage>, Error> {
+    single(
+        get_videos_comments(client, std::slice::from_ref(&video_id))?.found,
+        &video_id,
+    )
 }
 
 pub fn get_videos_chapters(
     client: &GQLClient,
-    video_ids: &Vec<String>,
-) -> Result<HashMap<String, Vec<VodChapter>>, ExitMsg> {
+    video_ids: &[String],
+) -> Result<BatchResult<VodChapter>, Error> {
     // Get all videos from a list of channels
 
     log::debug!("getting video(s) chapter(s)");
 
-    batched_query::<TwitchVideo, VodChapter>(
-        Box::new(|alias, id, after| {
-            formatdoc! {"
-                _{}: video( id: \"{}\" ) {{
-                    id title createdAt broadcastType status lengthSeconds
-                    moments(
-                        after: \"{}\", first: 100,
-                        momentRequestType: VIDEO_CHAPTER_MARKERS
-                    ) {{
-                        pageInfo {{ hasNextPage }}
-                        edges {{ cursor node {{
-                            description
-                            positionMilliseconds
-                            durationMilliseconds
-                }}  }}  }}  }}",
-                alias, id, after
-            }
-        }),
-        client,
-        video_ids,
-        Box::new(|_: &GQLClient, v: &TwitchVideo, r: &mut Vec<VodChapter>| {
-            let u = v.moments.as_ref().unwrap();
-            let mut after = "".to_owned();
-
-            for s in &u.edges {
-                r.push(VodChapter::from_data(&s.node));
-
-                if let Some(c) = s.cursor.to_owned() {
-                    after = c;
-                }
-            }
-
-            Ok((u.page_info.has_next_page, after))
-        }),
-    )
+    batched_query(videos_chapters(), client, video_ids)
 }
 
-pub fn get_video_chapters(
-    client: &GQLClient,
-    video_id: String,
-) -> Result<Vec<VodChapter>, ExitMsg> {
-    Ok(get_videos_chapters(client, &vec![video_id])?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+pub fn get_video_chapters(client: &GQLClient, video_id: String) -> Result<Vec<VodChapter>, Error> {
+    single(
+        get_videos_chapters(client, std::slice::from_ref(&video_id))?.found,
+        &video_id,
+    )
 }
 
 pub fn get_videos_playback_access_tokens(
     client: &GQLClient,
-    video_ids: &Vec<String>,
-) -> Result<HashMap<String, PlaybackAccessToken>, ExitMsg> {
+    video_ids: &[String],
+) -> Result<HashMap<String, PlaybackAccessToken>, Error> {
     // Get all video access tokens from a list of video ids
 
     log::debug!("getting video(s) pbat(s)");
 
-    let j = batched_query::<TwitchPlaybackAccessTokenToken, PlaybackAccessToken>(
-        Box::new(|alias, id, after| {
-            formatdoc! {"
-                _{}: video(id: \"{}{}\") {{
-                    playbackAccessToken(
-                        params: {{platform:\"web\",playerType:\"site\",playerBackend:\"mediaplayer\"}}
-                    ) {{ value signature }}
-                }}",
-                alias, id, after
-            }
-        }),
-        client,
-        video_ids,
-        Box::new(
-            |_: &GQLClient,
-             v: &TwitchPlaybackAccessTokenToken,
-             r: &mut Vec<PlaybackAccessToken>| {
-                r.push(PlaybackAccessToken::from_data(&v.playback_access_token));
-                Ok((false, "".to_owned()))
-            },
-        ),
-    )?;
-
-    Ok(j.into_iter()
-        .filter(|f| f.1.len() != 0)
-        .map(|(k, v)| (k, v.last().unwrap().to_owned()))
-        .collect())
+    let j = batched_query(videos_playback_access_tokens(), client, video_ids)?;
+
+    Ok(tokens(j))
 }
 
 pub fn get_video_playback_access_token(
     client: &GQLClient,
     video_id: String,
-) -> Result<PlaybackAccessToken, ExitMsg> {
-    Ok(get_videos_playback_access_tokens(client, &vec![video_id])?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+) -> Result<PlaybackAccessToken, Error> {
+    single(
+        get_videos_playback_access_tokens(client, std::slice::from_ref(&video_id))?,
+        &video_id,
+    )
 }
 
 pub fn get_clips_playback_access_tokens(
     client: &GQLClient,
-    clip_slugs: &Vec<String>,
-) -> Result<HashMap<String, PlaybackAccessToken>, ExitMsg> {
+    clip_slugs: &[String],
+) -> Result<HashMap<String, PlaybackAccessToken>, Error> {
     // Get all video access tokens from a list of video ids
 
     log::debug!("getting clip(s) pbat(s)");
 
-    let j = batched_query::<TwitchPlaybackAccessTokenToken, PlaybackAccessToken>(
-        Box::new(|alias, id, after| {
-            formatdoc! {"
-                _{}: clip(slug: \"{}{}\") {{
-                    playbackAccessToken(
-                        params: {{platform:\"web\",playerType:\"site\",playerBackend:\"mediaplayer\"}}
-                    ) {{ value signature }}
-                }}",
-                alias, id, after
-            }
-        }),
-        client,
-        clip_slugs,
-        Box::new(
-            |_: &GQLClient,
-             v: &TwitchPlaybackAccessTokenToken,
-             r: &mut Vec<PlaybackAccessToken>| {
-                r.push(PlaybackAccessToken::from_data(&v.playback_access_token));
-                Ok((false, "".to_owned()))
-            },
-        ),
-    )?;
-
-    Ok(j.into_iter()
-        .filter(|f| f.1.len() != 0)
-        .map(|(k, v)| (k, v.last().unwrap().to_owned()))
-        .collect())
+    let j = batched_query(clips_playback_access_tokens(), client, clip_slugs)?;
+
+    Ok(tokens(j))
 }
 
 pub fn get_clip_playback_access_token(
     client: &GQLClient,
     clip_slug: String,
-) -> Result<PlaybackAccessToken, ExitMsg> {
-    Ok(get_clips_playback_access_tokens(client, &vec![clip_slug])?
-        .values()
-        .last()
-        .unwrap()
-        .to_owned())
+) -> Result<PlaybackAccessToken, Error> {
+    single(
+        get_clips_playback_access_tokens(client, std::slice::from_ref(&clip_slug))?,
+        &clip_slug,
+    )
 }
 
-pub fn get_channel(client: &GQLClient, user_login: String) -> Result<Option<TwitchUser>, ExitMsg> {
+// The documents for the single lookups, each aliased `_`
+pub(crate) fn channel_query(user_login: String) -> String {
+    document(&[Field::new("user")
+        .alias("_")
+        .arg("login", user_login)
+        .of::<TwitchUser>()
+        .select(vec![
+            Field::new("roles").of::<TwitchUserRoles>(),
+            Field::new("stream").of::<TwitchUserStream>(),
+        ])])
+}
+
+pub(crate) fn video_query(video_id: String) -> String {
+    document(&[Field::new("video")
+        .alias("_")
+        .arg("id", video_id)
+        .of::<TwitchVideo>()])
+}
+
+pub(crate) fn clip_query(clip_slug: String) -> String {
+    document(&[Field::new("clip")
+        .alias("_")
+        .arg("slug", clip_slug)
+        .of::<TwitchClip>()])
+}
+
+pub(crate) fn only<T: TwitchData>(j: TwitchResponse<T>) -> Option<T> {
+    j.data.map(|mut f| f.remove("_").unwrap()).unwrap()
+}
+
+pub fn get_channel(client: &GQLClient, user_login: String) -> Result<Option<TwitchUser>, Error> {
     // Get channel info
 
     log::trace!("getting channel info for {}", user_login);
 
-    Ok(client
-        .query::<TwitchUser>(formatdoc! {"
-            {{  _: user( login: \"{}\" ) {{
-                id login displayName description createdAt
-                roles {{ isAffiliate isPartner }}
-                stream {{
-                    id title type viewersCount
-                    createdAt game {{ id name }}
-            }}  }}  }}", user_login
-        })?
-        .data
-        .map(|f| f.get("_").unwrap().to_owned())
-        .unwrap())
-}
-
-pub fn get_video(client: &GQLClient, video_id: String) -> Result<Option<TwitchVideo>, ExitMsg> {
+    Ok(only(client.query(channel_query(user_login))?))
+}
+
+pub fn get_video(client: &GQLClient, video_id: String) -> Result<Option<TwitchVideo>, Error> {
     // Get video info
 
     log::trace!("getting video info for {}", video_id);
 
-    Ok(client
-        .query::<TwitchVideo>(formatdoc! {"
-            {{  _: video( id: \"{}\" ) {{
-                id title createdAt status
-                broadcastType lengthSeconds
-                game {{ id name }}
-                creator {{ id login displayName }}
-            }}  }}", video_id
-        })?
-        .data
-        .map(|f| f.get("_").unwrap().to_owned())
-        .unwrap())
-}
-
-pub fn get_clip(client: &GQLClient, clip_slug: String) -> Result<Option<TwitchClip>, ExitMsg> {
+    Ok(only(client.query(video_query(video_id))?))
+}
+
+pub fn get_clip(client: &GQLClient, clip_slug: String) -> Result<Option<TwitchClip>, Error> {
     // Get clip info
 
     log::trace!("getting clip info for {}", clip_slug);
 
-    Ok(client
-        .query::<TwitchClip>(formatdoc! {"
-            {{  _: clip( slug: \"{}\" ) {{
-                id slug title createdAt viewCount
-                durationSeconds videoOffsetSeconds
-                video {{ id }} game {{ id name }}
-                videoQualities {{ frameRate quality sourceURL }}
-                broadcaster {{ id displayName login }}
-                curator {{ id displayName login }}
-            }}  }}", clip_slug
-        })?
-        .data
-        .map(|f| f.get("_").unwrap().to_owned())
-        .unwrap())
+    Ok(only(client.query(clip_query(clip_slug))?))
 }
@@ -5,6 +5,8 @@ use std::collections::HashMap;
 
 use serde::Deserialize;
 
+use crate::gql_query::{fields, Field, Selection};
+
 // TODO: change all from's to not use references?
 
 pub trait TwitchData {}
@@ -56,6 +58,37 @@ impl TwitchData for TwitchVideo {}
 impl TwitchNode for TwitchVideo {}
 impl TwitchNode for TwitchVideoMoment {}
 
+// Comments and moments are paged connections, so queries ask for them
+// separately along with their arguments
+impl Selection for TwitchVideo {
+    fn selection() -> Vec<Field> {
+        let mut f = fields(&[
+            "id",
+            "title",
+            "createdAt",
+            "broadcastType",
+            "status",
+            "lengthSeconds",
+        ]);
+        f.push(Field::new("game").of::<TwitchGame>());
+        f
+    }
+}
+impl Selection for TwitchGame {
+    fn selection() -> Vec<Field> {
+        fields(&["id", "name"])
+    }
+}
+impl Selection for TwitchVideoMoment {
+    fn selection() -> Vec<Field> {
+        fields(&[
+            "description",
+            "positionMilliseconds",
+            "durationMilliseconds",
+        ])
+    }
+}
+
 structstruck::strike! {
     #[strikethrough[derive(Debug, Deserialize, Clone)]]
     #[strikethrough[serde(rename_all = "camelCase")]]
@@ -76,6 +109,37 @@ structstruck::strike! {
 }
 impl TwitchNode for TwitchVideoComment {}
 
+impl Selection for TwitchVideoComment {
+    fn selection() -> Vec<Field> {
+        vec![
+            Field::new("contentOffsetSeconds"),
+            Field::new("commenter").of::<TwitchVideoCommentUser>(),
+            Field::new("message").of::<TwitchVideoCommentMessage>(),
+        ]
+    }
+}
+impl Selection for TwitchVideoCommentUser {
+    fn selection() -> Vec<Field> {
+        fields(&["displayName"])
+    }
+}
+impl Selection for TwitchVideoCommentMessage {
+    fn selection() -> Vec<Field> {
+        vec![
+            Field::new("userColor"),
+            Field::new("fragments").of::<TwitchVideoCommentFragment>(),
+        ]
+    }
+}
+impl Selection for TwitchVideoCommentFragment {
+    fn selection() -> Vec<Field> {
+        vec![
+            Field::new("text"),
+            Field::new("mention").of::<TwitchVideoCommentUser>(),
+        ]
+    }
+}
+
 structstruck::strike! {
     #[strikethrough[derive(Debug, Deserialize, Clone)]]
     #[strikethrough[serde(rename_all = "camelCase")]]
@@ -86,7 +150,7 @@ structstruck::strike! {
         pub created_at: String,
         pub view_count: usize,
         pub duration_seconds: usize,
-        pub video_offset_secconds: Option<usize>,
+        pub video_offset_seconds: Option<usize>,
         pub video: Option<pub struct TwitchClipVideoSource {
             pub id: String,
         }>,
@@ -103,6 +167,37 @@ structstruck::strike! {
 impl TwitchData for TwitchClip {}
 impl TwitchNode for TwitchClip {}
 
+impl Selection for TwitchClip {
+    fn selection() -> Vec<Field> {
+        let mut f = fields(&[
+            "id",
+            "slug",
+            "title",
+            "createdAt",
+            "viewCount",
+            "durationSeconds",
+            "videoOffsetSeconds",
+        ]);
+        f.extend([
+            Field::new("video").of::<TwitchClipVideoSource>(),
+            Field::new("game").of::<TwitchGame>(),
+            Field::new("curator").of::<TwitchUser>(),
+            Field::new("videoQualities").of::<TwitchClipVideoQuality>(),
+        ]);
+        f
+    }
+}
+impl Selection for TwitchClipVideoSource {
+    fn selection() -> Vec<Field> {
+        fields(&["id"])
+    }
+}
+impl Selection for TwitchClipVideoQuality {
+    fn selection() -> Vec<Field> {
+        fields(&["frameRate", "quality", "sourceURL"])
+    }
+}
+
 structstruck::strike! {
     #[strikethrough[derive(Debug, Deserialize, Clone)]]
     #[strikethrough[serde(rename_all = "camelCase")]]
@@ -116,6 +211,14 @@ structstruck::strike! {
         }<T>>,
     }
 }
+impl<T: TwitchNode + Selection> Selection for TwitchConnection<T> {
+    fn selection() -> Vec<Field> {
+        vec![
+            Field::new("pageInfo").select(fields(&["hasNextPage"])),
+            Field::new("edges").select(vec![Field::new("cursor"), Field::new("node").of::<T>()]),
+        ]
+    }
+}
 
 structstruck::strike! {
     #[strikethrough[derive(Debug, Deserialize, Clone)]]
@@ -142,6 +245,26 @@ structstruck::strike! {
 }
 impl TwitchData for TwitchUser {}
 
+// Just who the user is, roles, streams, videos and clips are asked for by the
+// queries that want them
+impl Selection for TwitchUser {
+    fn selection() -> Vec<Field> {
+        fields(&["id", "login", "displayName"])
+    }
+}
+impl Selection for TwitchUserRoles {
+    fn selection() -> Vec<Field> {
+        fields(&["isAffiliate", "isPartner"])
+    }
+}
+impl Selection for TwitchUserStream {
+    fn selection() -> Vec<Field> {
+        let mut f = fields(&["id", "title", "type", "viewersCount", "createdAt"]);
+        f.push(Field::new("game").of::<TwitchGame>());
+        f
+    }
+}
+
 structstruck::strike! {
     #[strikethrough[derive(Debug, Deserialize, Clone)]]
     #[strikethrough[serde(rename_all = "camelCase")]]
@@ -154,6 +277,12 @@ structstruck::strike! {
 }
 impl TwitchData for TwitchPlaybackAccessTokenToken {}
 
+impl Selection for TwitchPlaybackAccessToken {
+    fn selection() -> Vec<Field> {
+        fields(&["value", "signature"])
+    }
+}
+
 structstruck::strike! {
     #[strikethrough[derive(Debug, Deserialize, Clone)]]
     #[strikethrough[serde(rename_all = "camelCase")]]
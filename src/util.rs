@@ -2,104 +2,19 @@
 
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
 
-// Self-describing exit codes.
-// Each exit point of the program should be using a very clear exit code, along
-// with a message sent to stderr for more details. Certain codes may be reserved
-// or not used, as indicated by the leading underscore in its name.
-#[derive(Debug, Clone)]
-pub enum ExitCode {
-    // Special codes
-    _CleanExit,
-    Interrupted,
-    _ReservedByClap,
-    StderrLoggerError,
-
-    // Generic codes
-    CannotRegisterSignalHandler,
-    CannotCreateDir,
-    CannotChangeDir,
-
-    CannotOpenConfig,
-    CannotParseConfig,
-    CannotValidateConfig,
-
-    CannotConnectToTwitch,
-    RequestErrorFromTwitch, // TODO: rename this one
-    GQLErrorFromTwitch,
-    CannotParseResponseFromTwitch,
-
-    CannotStartFfmpeg,
-    FfmpegReturnedError,
-    FfmpegInterrupted,
-
-    CannotGlobDirectory,
-
-    // Command-specific codes
-    InitCannotOpenConfig,
-    InitCannotWriteConfig,
-    InitCannotSerializeConfig,
-
-    PullCannotGetPlaylistURI,
-    PullCannotReadPlaylistURI,
-    PullCannotParsePlaylistURI,
-    PullCannotFindPlaylistURI,
-    PullCannotGetSourcePlaylist,
-    PullCannotReadSourcePlaylist,
-    PullCannotParseSourcePlaylist,
-    PullCannotUseSourcePlaylist,
-    PullCannotWriteSourcePlaylist,
-    PullCannotCleanUpAfterDownload,
-    PullCannotGetChunk,
-    PullCannotParseChunk,
-    PullCannotWriteChunk,
-    PullCannotOpenMeta,
-    PullFailedToRecieveOnChannel,
-    PullFailedToSendOnChannel,
-}
+use crate::config::Config;
+use crate::error::Error;
 
-#[derive(Debug, Clone)]
-pub struct ExitMsg {
-    pub code: ExitCode,
-    pub msg: String,
-}
-impl ExitMsg {
-    pub fn new(code: ExitCode, msg: String) -> Self {
-        log::warn!(
-            "ExitMsg - {:?} ({}) - {}",
-            code,
-            code.clone() as i32,
-            msg.as_str()
-        );
-        ExitMsg {
-            code: code,
-            msg: msg,
-        }
-    }
-}
-impl std::fmt::Display for ExitMsg {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{} Exit code: {:?} ({})",
-            self.msg.as_str(),
-            self.code,
-            self.code.clone() as i32
-        )
-    }
-}
-impl std::error::Error for ExitMsg {}
-
-pub fn create_dir(dir_path: &Path) -> Result<(), ExitMsg> {
+pub fn create_dir(dir_path: &Path) -> Result<(), Error> {
     fs::create_dir_all(&dir_path).map_err(|why| {
-        ExitMsg::new(
-            ExitCode::CannotCreateDir,
-            format!(
-                "Cannot create directory `{}`, reason: \"{}\".",
-                &dir_path.display(),
-                why
-            ),
-        )
+        Error::io(format!(
+            "Cannot create directory `{}`, reason: \"{}\".",
+            &dir_path.display(),
+            why
+        ))
+        .with_source(why)
     })
 }
 
@@ -130,48 +45,149 @@ pub fn format_size(size: usize, digits: usize, display_units: bool) -> String {
     }
 }
 
-pub fn chdir(path: &PathBuf) -> Result<(), ExitMsg> {
+pub fn format_duration(seconds: usize) -> String {
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+// Print rows as a plain text table, with each column padded to its widest cell.
+pub fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|f| f.chars().count()).collect();
+    for r in rows {
+        for (i, c) in r.iter().enumerate() {
+            widths[i] = widths[i].max(c.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let s: Vec<_> = cells
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{: <1$}", c, widths[i]))
+            .collect();
+        println!("{}", s.join("  ").trim_end());
+    };
+
+    line(headers.to_vec());
+    for r in rows {
+        line(r.iter().map(|f| f.as_str()).collect());
+    }
+}
+
+pub fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_owned()
+    } else {
+        s.chars().take(max.saturating_sub(3)).collect::<String>() + "..."
+    }
+}
+
+pub fn chdir(path: &PathBuf) -> Result<(), Error> {
     log::debug!("changing directory to {}", path.to_str().unwrap());
     std::env::set_current_dir(path).map_err(|why| {
-        ExitMsg::new(
-            ExitCode::CannotChangeDir,
-            format!(
-                "Cannot change directory to `{}`, reason: \"{}\".",
-                path.to_str().unwrap(),
-                why
-            ),
-        )
+        Error::io(format!(
+            "Cannot change directory to `{}`, reason: \"{}\".",
+            path.to_str().unwrap(),
+            why
+        ))
+        .with_source(why)
     })?;
 
     Ok(())
 }
 
-pub fn get_meta_ids(path: PathBuf) -> Result<Vec<String>, ExitMsg> {
-    let path = path.join("*.meta.json");
-    let path = path.to_str().unwrap();
-
-    // strings we use in making the file name, they have standard sizes so we reference them here.
-    let extension = ".meta.json";
-    let timestamp_base = "1970-01-01T00;00;00Z";
-
-    // TODO: remove glob and just list_dir manually
+// Find all meta files somewhere under `path`. The layout is user-defined
+// through templates, so we can't assume any depth or naming.
+pub fn get_meta_files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let pattern = PathBuf::from(glob::Pattern::escape(path.to_str().unwrap()))
+        .join("**")
+        .join("*.meta.json");
 
-    Ok(glob::glob(path)
+    Ok(glob::glob(pattern.to_str().unwrap())
         .map_err(|why| {
-            ExitMsg::new(
-                ExitCode::CannotGlobDirectory,
-                format!("Failed to glob/wildcard directory, reason `{}`.", why),
-            )
+            Error::io(format!(
+                "Failed to glob/wildcard directory, reason `{}`.",
+                why
+            ))
+            .with_source(why)
         })?
         .filter_map(|f| f.ok())
-        .map(|f| {
-            let s = f.file_name().unwrap().to_str().unwrap();
-            // slice out the timestamp at the front, and the extension at the back, for just the id.
-            (&s[timestamp_base.len()..(s.len() - extension.len())]).to_owned()
-        })
         .collect())
 }
 
+// Run ffmpeg with the given arguments, using the log level and stderr
+// destination from the config, and turn any failure into an error.
+pub fn run_ffmpeg(conf: &Config, args: &[&str]) -> Result<(), Error> {
+    ffmpeg_status(ffmpeg_command(conf, args)?.status())
+}
+
+// The ffmpeg command `run_ffmpeg` runs, for running it some other way
+pub fn ffmpeg_command(conf: &Config, args: &[&str]) -> Result<Command, Error> {
+    let loglevel = format!("{:?}", conf.export.ffmpeg_loglevel).to_lowercase();
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(args)
+        .args(["-y", "-stats", "-loglevel", &loglevel]);
+
+    if let Some(p) = &conf.export.ffmpeg_stderr {
+        let f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(p)
+            .map_err(|why| {
+                Error::ffmpeg(format!(
+                    "Failed to open FFMPEG log `{}`, reason \"{}\".",
+                    p.display(),
+                    why
+                ))
+                .with_source(why)
+            })?;
+        cmd.stderr(f);
+    }
+
+    log::debug!("running ffmpeg {:?}", args);
+    Ok(cmd)
+}
+
+pub fn ffmpeg_status(status: std::io::Result<ExitStatus>) -> Result<(), Error> {
+    let status = status.map_err(|why| {
+        Error::ffmpeg(format!("Failed to start FFMPEG, reason \"{}\".", why)).with_source(why)
+    })?;
+
+    // check that ffmpeg returned as expected, raise error if necessary
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(s) => Err(Error::ffmpeg(format!(
+            "FFMPEG returned a non-zero status, `{}`.",
+            s
+        ))),
+        None => Err(Error::ffmpeg("FFMPEG was interrupted, no other error.")),
+    }
+}
+
+// Paths go through two rounds of unescaping in ffmpeg, once as a filter option
+// and once as part of the filtergraph, so they're escaped for each in turn.
+// https://ffmpeg.org/ffmpeg-filters.html#Notes-on-filtergraph-escaping
+pub fn ffmpeg_filter_path(path: &Path) -> String {
+    let path = path.to_str().unwrap().replace('\\', "/");
+    let option = escape_chars(&path, &['\\', '\'', ':']);
+    escape_chars(&option, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+fn escape_chars(s: &str, special: &[char]) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
 pub fn from_vodbot_dir(dirs: &[&str]) -> PathBuf {
     let mut path = dirs::config_dir().unwrap();
     path.push("vodbot");
@@ -3,18 +3,40 @@
 // use crate::twitch_api;
 
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use crate::config::ConfigDirectories;
+use crate::error::Error;
+use crate::template::{format_timestamp, render_path};
 use crate::twitch_api::{
     TwitchClip, TwitchPlaybackAccessToken, TwitchUser, TwitchVideo, TwitchVideoComment,
     TwitchVideoMoment,
 };
+use crate::util::create_dir;
 
 // Tag trait
 pub trait VodBotData {
     fn identifier(&self) -> String;
     fn created_at(&self) -> String;
-    fn filename(&self) -> String;
+    fn extension(&self) -> &'static str;
+    fn template_value(&self, name: &str, spec: Option<&str>) -> Option<String>;
+
+    // Path of the content relative to its content directory, built from the
+    // directory and filename templates in the config.
+    fn output_path(&self, dirs: &ConfigDirectories) -> Result<PathBuf, Error> {
+        let lookup = |n: &str, s: Option<&str>| self.template_value(n, s);
+        let dir = render_path(&dirs.directory_template, lookup)?;
+        let file = render_path(&dirs.filename_template, lookup)?;
+        let name = file.file_name().and_then(|f| f.to_str()).ok_or_else(|| {
+            Error::input(format!(
+                "Filename template `{}` rendered to an empty name.",
+                dirs.filename_template
+            ))
+        })?;
+        let name = format!("{}.{}", name, self.extension());
+        Ok(dir.join(file.with_file_name(name)))
+    }
 }
 
 // Pull related data
@@ -87,12 +109,21 @@ impl VodBotData for Vod {
     fn created_at(&self) -> String {
         self.created_at.clone()
     }
-    fn filename(&self) -> String {
-        format!(
-            "{}_{}.mkv",
-            self.created_at().replace(":", ";"),
-            self.identifier()
-        )
+    fn extension(&self) -> &'static str {
+        "mkv"
+    }
+    fn template_value(&self, name: &str, spec: Option<&str>) -> Option<String> {
+        match (name, spec) {
+            ("id", None) => Some(self.id.clone()),
+            ("streamer_id", None) => Some(self.streamer_id.clone()),
+            ("streamer_login", None) => Some(self.streamer_login.clone()),
+            ("streamer_name", None) => Some(self.streamer_name.clone()),
+            ("game_id", None) => Some(self.game_id.clone()),
+            ("game_name", None) => Some(self.game_name.clone()),
+            ("title", None) => Some(self.title.clone()),
+            ("created_at", s) => format_timestamp(&self.created_at, s),
+            _ => None,
+        }
     }
 }
 
@@ -159,7 +190,7 @@ impl Clip {
             title: n.title.to_owned(),
             created_at: n.created_at.to_owned(),
             duration: n.duration_seconds,
-            offset: n.video_offset_secconds.unwrap_or(0),
+            offset: n.video_offset_seconds.unwrap_or(0),
             view_count: n.view_count,
             vod_id: n
                 .video
@@ -177,12 +208,24 @@ impl VodBotData for Clip {
     fn created_at(&self) -> String {
         self.created_at.clone()
     }
-    fn filename(&self) -> String {
-        format!(
-            "{}_{}.mp4",
-            self.created_at().replace(":", ";"),
-            self.identifier()
-        )
+    fn extension(&self) -> &'static str {
+        "mp4"
+    }
+    fn template_value(&self, name: &str, spec: Option<&str>) -> Option<String> {
+        match (name, spec) {
+            // clips are identified by their slug everywhere else, so `{id}` follows suit
+            ("id", None) => Some(self.slug.clone()),
+            ("streamer_id", None) => Some(self.streamer_id.clone()),
+            ("streamer_login", None) => Some(self.streamer_login.clone()),
+            ("streamer_name", None) => Some(self.streamer_name.clone()),
+            ("clipper_login", None) => Some(self.clipper_login.clone()),
+            ("clipper_name", None) => Some(self.clipper_name.clone()),
+            ("game_id", None) => Some(self.game_id.clone()),
+            ("game_name", None) => Some(self.game_name.clone()),
+            ("title", None) => Some(self.title.clone()),
+            ("created_at", s) => format_timestamp(&self.created_at, s),
+            _ => None,
+        }
     }
 }
 
@@ -199,11 +242,86 @@ pub struct Channel {
 #[serde(rename_all = "camelCase")]
 pub struct ChatLog {
     pub video_id: String,
-    // pub streamer_id: String,
-    // pub streamer_login: String,
-    // pub streamer_name: String,
+    pub streamer_id: String,
+    pub streamer_login: String,
+    pub streamer_name: String,
+
+    pub game_id: String,
+    pub game_name: String,
+
+    pub title: String,
+    pub created_at: String,
+    pub message_count: usize,
+    #[serde(default)]
     pub messages: Vec<ChatMessage>,
 }
+impl ChatLog {
+    pub fn from_data(v: &Vod, m: Vec<ChatMessage>) -> Self {
+        Self {
+            video_id: v.id.clone(),
+            streamer_id: v.streamer_id.clone(),
+            streamer_login: v.streamer_login.clone(),
+            streamer_name: v.streamer_name.clone(),
+            game_id: v.game_id.clone(),
+            game_name: v.game_name.clone(),
+            title: v.title.clone(),
+            created_at: v.created_at.clone(),
+            message_count: m.len(),
+            messages: m,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file = fs::File::open(path).map_err(|why| {
+            Error::io(format!(
+                "Failed to open chat log at `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+
+        serde_json::from_reader(file).map_err(|why| {
+            Error::parse(format!(
+                "Failed to parse chat log at `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })
+    }
+
+    // Everything but the messages, which is what goes in the meta file and index
+    pub fn meta(&self) -> serde_json::Value {
+        let mut v = serde_json::to_value(self).unwrap();
+        v.as_object_mut().unwrap().remove("messages");
+        v
+    }
+}
+impl VodBotData for ChatLog {
+    fn identifier(&self) -> String {
+        self.video_id.clone()
+    }
+    fn created_at(&self) -> String {
+        self.created_at.clone()
+    }
+    fn extension(&self) -> &'static str {
+        "chat.json"
+    }
+    fn template_value(&self, name: &str, spec: Option<&str>) -> Option<String> {
+        match (name, spec) {
+            ("id", None) => Some(self.video_id.clone()),
+            ("streamer_id", None) => Some(self.streamer_id.clone()),
+            ("streamer_login", None) => Some(self.streamer_login.clone()),
+            ("streamer_name", None) => Some(self.streamer_name.clone()),
+            ("game_id", None) => Some(self.game_id.clone()),
+            ("game_name", None) => Some(self.game_name.clone()),
+            ("title", None) => Some(self.title.clone()),
+            ("created_at", s) => format_timestamp(&self.created_at, s),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -246,25 +364,105 @@ pub struct VideoSlice {
     pub filepath: PathBuf,
 }
 
-// #[derive(Debug, Serialize, Deserialize, Clone)]
-// #[serde(rename_all = "camelCase")]
-// pub struct ThumbnailData {
-//     pub heads: Vec<String>,
-//     pub game: String,
-//     pub text: String,
-//     pub video_slice_idx: usize,
-//     pub timestamp: String,
-// }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ThumbnailData {
+    pub heads: Vec<String>,
+    pub game: String,
+    pub text: String,
+    pub video_slice_idx: usize,
+    pub timestamp: String,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct StageData {
+    pub id: String,
     pub title: String,
     pub description: String,
     pub streamers: Vec<String>,
-    // pub thumbnail: Option<ThumbnailData>,
+    #[serde(default)]
+    pub thumbnail: Option<ThumbnailData>,
+    // Export preset to use instead of the config's default
+    #[serde(default)]
+    pub preset: Option<String>,
     pub slices: Vec<VideoSlice>,
 }
+impl StageData {
+    pub fn path(dirs: &ConfigDirectories, id: &str) -> PathBuf {
+        dirs.stage.join(format!("{}.stage.json", id))
+    }
+
+    pub fn load(dirs: &ConfigDirectories, id: &str) -> Result<Self, Error> {
+        let path = Self::path(dirs, id);
+        let file = fs::File::open(&path).map_err(|why| {
+            Error::io(format!(
+                "Failed to open stage `{}`, reason: \"{}\".",
+                id, why
+            ))
+            .with_source(why)
+        })?;
+
+        serde_json::from_reader(file).map_err(|why| {
+            Error::parse(format!(
+                "Failed to parse stage at `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })
+    }
+
+    pub fn load_all(dirs: &ConfigDirectories) -> Result<Vec<Self>, Error> {
+        let mut stages = Vec::new();
+        // nothing has been staged yet on a fresh install
+        if !dirs.stage.exists() {
+            return Ok(stages);
+        }
+        let entries = fs::read_dir(&dirs.stage).map_err(|why| {
+            Error::io(format!(
+                "Failed to read stage directory `{}`, reason: \"{}\".",
+                dirs.stage.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+        for e in entries.filter_map(|f| f.ok()) {
+            let name = e.file_name().to_str().unwrap_or("").to_owned();
+            if let Some(id) = name.strip_suffix(".stage.json") {
+                stages.push(Self::load(dirs, id)?);
+            }
+        }
+        stages.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(stages)
+    }
+
+    pub fn remove(&self, dirs: &ConfigDirectories) -> Result<(), Error> {
+        let path = Self::path(dirs, &self.id);
+        fs::remove_file(&path).map_err(|why| {
+            Error::io(format!(
+                "Failed to remove stage at `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })
+    }
+
+    pub fn save(&self, dirs: &ConfigDirectories) -> Result<(), Error> {
+        create_dir(&dirs.stage)?;
+        let path = Self::path(dirs, &self.id);
+        let json = serde_json::to_string_pretty(self).unwrap();
+        fs::write(&path, json).map_err(|why| {
+            Error::io(format!(
+                "Failed to write stage to `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -0,0 +1,521 @@
+// YouTube client, for uploading videos with the resumable upload protocol.
+// https://developers.google.com/youtube/v3/guides/using_resumable_upload_protocol
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{
+    AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, LOCATION, RANGE,
+};
+use reqwest::redirect::Policy;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, YouTubePrivacy};
+use crate::error::Error;
+use crate::oauth;
+use crate::progress::{DownloadProgress, ProgressObserver};
+
+// Chunks must be a multiple of this, except for the last one
+const CHUNK_GRANULARITY: usize = 256 * 1024;
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoMetadata {
+    pub snippet: VideoSnippet,
+    pub status: VideoStatus,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoSnippet {
+    pub title: String,
+    pub description: String,
+    pub category_id: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoStatus {
+    pub privacy_status: YouTubePrivacy,
+    pub self_declared_made_for_kids: bool,
+}
+
+impl VideoMetadata {
+    pub fn new(title: &str, description: &str, privacy: YouTubePrivacy) -> Self {
+        // YouTube refuses angle brackets, and titles over 100 characters
+        let clean = |s: &str| s.replace('<', "‹").replace('>', "›");
+        Self {
+            snippet: VideoSnippet {
+                title: clean(title).chars().take(100).collect(),
+                description: clean(description).chars().take(5000).collect(),
+                category_id: "20".to_owned(), // Gaming
+            },
+            status: VideoStatus {
+                privacy_status: privacy,
+                self_declared_made_for_kids: false,
+            },
+        }
+    }
+}
+
+// What we persist between runs, so an interrupted upload can pick up where it left off
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadState {
+    uri: String,
+    file: PathBuf,
+    size: u64,
+}
+
+enum UploadProgress {
+    Offset(u64),
+    Done(String),
+    Expired,
+}
+
+#[derive(Deserialize)]
+struct UploadedVideo {
+    id: String,
+}
+
+pub struct YouTubeClient<'a> {
+    conf: &'a Config,
+    api_url: String,
+    // Swapped for a fresh one if YouTube says it ran out mid-upload
+    access_token: RefCell<String>,
+    retries: usize,
+    client: Client,
+}
+impl<'a> YouTubeClient<'a> {
+    pub fn new(conf: &'a Config, access_token: String) -> Self {
+        Self {
+            conf,
+            api_url: conf.upload.api_url.trim_end_matches('/').to_owned(),
+            access_token: RefCell::new(access_token),
+            retries: conf.pull.connection_retries,
+            // 308 means "resume incomplete" here, never a redirect, and big
+            // chunks on slow connections can take a while
+            client: Client::builder()
+                .redirect(Policy::none())
+                .timeout(None)
+                .build()
+                .unwrap(),
+        }
+    }
+
+    fn bearer(&self) -> String {
+        format!("Bearer {}", self.access_token.borrow())
+    }
+
+    // Access tokens only last about an hour, a long upload can outlive one
+    fn refresh(&self) -> Result<(), Error> {
+        log::info!("youtube access token was turned down, refreshing it");
+        *self.access_token.borrow_mut() = oauth::refresh(self.conf)?.access_token;
+        Ok(())
+    }
+
+    // Upload a video, resuming a previous attempt if `state_path` has one
+    // recorded for the same file. Returns the id of the new video. Progress
+    // goes to `observer` under the file's name, once for each chunk sent.
+    pub fn upload_video(
+        &self,
+        path: &Path,
+        meta: &VideoMetadata,
+        chunk_size: usize,
+        notify_subscribers: bool,
+        state_path: &Path,
+        observer: &dyn ProgressObserver,
+    ) -> Result<String, Error> {
+        let id = self.send_video(
+            path,
+            meta,
+            chunk_size,
+            notify_subscribers,
+            state_path,
+            observer,
+        )?;
+        // done, so there's nothing left to resume
+        let _ = fs::remove_file(state_path);
+        Ok(id)
+    }
+
+    fn send_video(
+        &self,
+        path: &Path,
+        meta: &VideoMetadata,
+        chunk_size: usize,
+        notify_subscribers: bool,
+        state_path: &Path,
+        observer: &dyn ProgressObserver,
+    ) -> Result<String, Error> {
+        let size = fs::metadata(path)
+            .map_err(|why| {
+                Error::io(format!(
+                    "Failed to read `{}`, reason: \"{}\".",
+                    path.display(),
+                    why
+                ))
+                .with_source(why)
+            })?
+            .len();
+        let chunk_size = ((chunk_size / CHUNK_GRANULARITY).max(1) * CHUNK_GRANULARITY) as u64;
+
+        let mut state = None;
+        if let Ok(s) = fs::read_to_string(state_path) {
+            match serde_json::from_str::<UploadState>(&s) {
+                Ok(s) if s.file == path && s.size == size => state = Some(s),
+                _ => log::warn!("ignoring stale upload state at {}", state_path.display()),
+            }
+        }
+
+        let mut offset = 0;
+        let state = match state {
+            Some(s) => match self.query_progress(&s.uri, size)? {
+                UploadProgress::Done(id) => return Ok(id),
+                UploadProgress::Offset(o) => {
+                    log::info!("resuming upload at {} of {} bytes", o, size);
+                    offset = o;
+                    s
+                }
+                UploadProgress::Expired => {
+                    log::info!("upload session expired, starting over");
+                    self.start_upload(path, size, meta, notify_subscribers, state_path)?
+                }
+            },
+            None => self.start_upload(path, size, meta, notify_subscribers, state_path)?,
+        };
+
+        let mut file = fs::File::open(path).map_err(|why| {
+            Error::io(format!(
+                "Failed to open `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+
+        let name = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or_default()
+            .to_owned();
+        let parts_total = size.div_ceil(chunk_size).max(1) as usize;
+        let (resumed_at, started) = (offset, Instant::now());
+        observer.started("Upload", &name);
+
+        let mut failures = 0;
+        let mut refreshed = false;
+        loop {
+            let end = (offset + chunk_size).min(size);
+            let mut chunk = vec![0u8; (end - offset) as usize];
+            file.seek(SeekFrom::Start(offset))
+                .and_then(|_| file.read_exact(&mut chunk))
+                .map_err(|why| {
+                    Error::io(format!(
+                        "Failed to read `{}`, reason: \"{}\".",
+                        path.display(),
+                        why
+                    ))
+                    .with_source(why)
+                })?;
+
+            let resp = self
+                .client
+                .put(&state.uri)
+                .header(AUTHORIZATION, self.bearer())
+                .header(
+                    CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", offset, end.max(1) - 1, size),
+                )
+                .body(chunk)
+                .send();
+
+            let progress = match resp {
+                // the chunk is sent again with a new token, but only once in
+                // a row so a revoked login still fails
+                Ok(r) if r.status() == StatusCode::UNAUTHORIZED && !refreshed => {
+                    self.refresh()?;
+                    refreshed = true;
+                    continue;
+                }
+                Ok(r) => match r.status() {
+                    s if s.is_server_error() => None,
+                    _ => Some(read_progress(r)?),
+                },
+                Err(why) => {
+                    log::warn!("chunk upload failed: {}", why);
+                    None
+                }
+            };
+
+            match progress {
+                Some(UploadProgress::Done(id)) => {
+                    let seconds = started.elapsed().as_secs_f32();
+                    observer.finished(&name, (size - resumed_at) as usize, seconds);
+                    return Ok(id);
+                }
+                Some(UploadProgress::Offset(o)) => {
+                    failures = 0;
+                    refreshed = false;
+                    offset = o;
+
+                    // only what was sent this run counts towards the speed
+                    let seconds = started.elapsed().as_secs_f32().max(0.001);
+                    let per_second = (offset - resumed_at) as f32 / seconds;
+                    observer.progress(&DownloadProgress {
+                        id: name.clone(),
+                        parts_done: (offset / chunk_size) as usize,
+                        parts_total,
+                        bytes: offset as usize,
+                        estimated_bytes: size as usize,
+                        bytes_per_second: per_second as usize,
+                        seconds_left: (size - offset) as f32 / per_second.max(1.0),
+                    });
+                }
+                Some(UploadProgress::Expired) => {
+                    let _ = fs::remove_file(state_path);
+                    return Err(Error::network(
+                        "Upload session expired mid-upload, try again.",
+                    ));
+                }
+                None => {
+                    failures += 1;
+                    if failures > self.retries {
+                        return Err(Error::network(format!(
+                            "Failed to upload chunk after {} tries, run upload again to resume.",
+                            failures
+                        )));
+                    }
+                    // back off, then ask the server what it actually got
+                    std::thread::sleep(Duration::from_secs(1 << failures.min(6)));
+                    match self.query_progress(&state.uri, size)? {
+                        UploadProgress::Offset(o) => offset = o,
+                        UploadProgress::Done(id) => {
+                            let seconds = started.elapsed().as_secs_f32();
+                            observer.finished(&name, (size - resumed_at) as usize, seconds);
+                            return Ok(id);
+                        }
+                        UploadProgress::Expired => {
+                            return Err(Error::network(
+                                "Upload session expired mid-upload, try again.",
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Attach a caption track to an uploaded video, sent as a multipart upload
+    pub fn insert_caption(
+        &self,
+        video_id: &str,
+        name: &str,
+        language: &str,
+        content: Vec<u8>,
+    ) -> Result<(), Error> {
+        let url = format!(
+            "{}/upload/youtube/v3/captions?uploadType=multipart&part=snippet",
+            self.api_url
+        );
+        let meta = serde_json::json!({
+            "snippet": {
+                "videoId": video_id,
+                "language": language,
+                "name": name,
+                "isDraft": false,
+            }
+        });
+
+        let boundary = format!("vodbot_{}", video_id);
+        let mut body = format!(
+            "--{b}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{}\r\n--{b}\r\nContent-Type: application/octet-stream\r\n\r\n",
+            meta,
+            b = boundary
+        )
+        .into_bytes();
+        body.extend(content);
+        body.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+
+        let resp = self
+            .client
+            .post(url)
+            .header(AUTHORIZATION, self.bearer())
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/related; boundary={}", boundary),
+            )
+            .body(body)
+            .send()
+            .map_err(|why| {
+                Error::network(format!("Failed to upload captions, reason: \"{}\".", why))
+                    .with_source(why)
+            })?;
+
+        if !resp.status().is_success() {
+            return Err(Error::network(format!(
+                "YouTube refused the captions ({}): \"{}\".",
+                resp.status(),
+                resp.text().unwrap_or_default()
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Set the custom thumbnail of an uploaded video, the channel must be verified
+    pub fn set_thumbnail(&self, video_id: &str, path: &Path) -> Result<(), Error> {
+        let image = fs::read(path).map_err(|why| {
+            Error::io(format!(
+                "Failed to read thumbnail `{}`, reason: \"{}\".",
+                path.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+
+        let resp = self
+            .client
+            .post(format!(
+                "{}/upload/youtube/v3/thumbnails/set?videoId={}",
+                self.api_url, video_id
+            ))
+            .header(AUTHORIZATION, self.bearer())
+            .header(CONTENT_TYPE, "image/png")
+            .body(image)
+            .send()
+            .map_err(|why| {
+                Error::network(format!("Failed to upload thumbnail, reason: \"{}\".", why))
+                    .with_source(why)
+            })?;
+
+        if !resp.status().is_success() {
+            return Err(Error::network(format!(
+                "YouTube refused the thumbnail ({}): \"{}\".",
+                resp.status(),
+                resp.text().unwrap_or_default()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn start_upload(
+        &self,
+        path: &Path,
+        size: u64,
+        meta: &VideoMetadata,
+        notify_subscribers: bool,
+        state_path: &Path,
+    ) -> Result<UploadState, Error> {
+        let url = format!(
+            "{}/upload/youtube/v3/videos?uploadType=resumable&part=snippet,status&notifySubscribers={}",
+            self.api_url, notify_subscribers
+        );
+        let resp = self
+            .client
+            .post(url)
+            .header(AUTHORIZATION, self.bearer())
+            .header("X-Upload-Content-Length", size)
+            .header("X-Upload-Content-Type", "video/*")
+            .json(meta)
+            .send()
+            .map_err(|why| {
+                Error::network(format!("Failed to start upload, reason: \"{}\".", why))
+                    .with_source(why)
+            })?;
+
+        if !resp.status().is_success() {
+            return Err(Error::network(format!(
+                "YouTube refused to start the upload ({}): \"{}\".",
+                resp.status(),
+                resp.text().unwrap_or_default()
+            )));
+        }
+
+        let uri = resp
+            .headers()
+            .get(LOCATION)
+            .and_then(|f| f.to_str().ok())
+            .ok_or_else(|| Error::network("YouTube did not return an upload session URI."))?
+            .to_owned();
+
+        let state = UploadState {
+            uri,
+            file: path.to_owned(),
+            size,
+        };
+        fs::write(state_path, serde_json::to_string(&state).unwrap()).map_err(|why| {
+            Error::io(format!(
+                "Failed to save upload state to `{}`, reason: \"{}\".",
+                state_path.display(),
+                why
+            ))
+            .with_source(why)
+        })?;
+
+        Ok(state)
+    }
+
+    fn query_progress(&self, uri: &str, size: u64) -> Result<UploadProgress, Error> {
+        let resp = self.send_query_progress(uri, size)?;
+        if resp.status() == StatusCode::UNAUTHORIZED {
+            self.refresh()?;
+            return read_progress(self.send_query_progress(uri, size)?);
+        }
+        read_progress(resp)
+    }
+
+    fn send_query_progress(&self, uri: &str, size: u64) -> Result<Response, Error> {
+        self.client
+            .put(uri)
+            .header(AUTHORIZATION, self.bearer())
+            .header(CONTENT_LENGTH, 0)
+            .header(CONTENT_RANGE, format!("bytes */{}", size))
+            .send()
+            .map_err(|why| {
+                Error::network(format!(
+                    "Failed to query upload status, reason: \"{}\".",
+                    why
+                ))
+                .with_source(why)
+            })
+    }
+}
+
+fn read_progress(resp: Response) -> Result<UploadProgress, Error> {
+    match resp.status() {
+        StatusCode::PERMANENT_REDIRECT => {
+            // `Range: bytes=0-N` is what the server has, no header means nothing yet
+            let next = resp
+                .headers()
+                .get(RANGE)
+                .and_then(|f| f.to_str().ok())
+                .and_then(|f| f.rsplit('-').next())
+                .and_then(|f| f.parse::<u64>().ok())
+                .map_or(0, |f| f + 1);
+            Ok(UploadProgress::Offset(next))
+        }
+        StatusCode::OK | StatusCode::CREATED => {
+            let v: UploadedVideo = resp.json().map_err(|why| {
+                Error::parse(format!(
+                    "Failed to parse upload response, reason: \"{}\".",
+                    why
+                ))
+                .with_source(why)
+            })?;
+            Ok(UploadProgress::Done(v.id))
+        }
+        StatusCode::NOT_FOUND | StatusCode::GONE => Ok(UploadProgress::Expired),
+        s => Err(Error::network(format!(
+            "Upload was rejected by YouTube ({}): \"{}\".",
+            s,
+            resp.text().unwrap_or_default()
+        ))),
+    }
+}